@@ -1,24 +1,31 @@
 use crate::domain::Target;
+use crate::paths::Paths;
 use anyhow::{Context, Result};
 use std::path::Path;
 
 pub fn clean_target_outputs(targets: &[Target]) -> Result<()> {
     for target in targets.iter() {
-        for output_path in &target.output_paths {
-            clean_path(output_path)?;
-        }
+        clean_paths(&target.output_paths)?;
+    }
+
+    Ok(())
+}
+
+pub fn clean_paths(paths: &Paths) -> Result<()> {
+    for path in paths.list_top_level_entries()? {
+        clean_path(&path)?;
     }
 
     Ok(())
 }
 
-fn clean_path(path: &Path) -> Result<()> {
+pub fn clean_path(path: &Path) -> Result<()> {
     if path.exists() {
         if path.is_file() {
-            std::fs::remove_file(&path)
+            std::fs::remove_file(path)
                 .with_context(|| format!("Failed to remove file {}", path.display()))?;
         } else if path.is_dir() {
-            std::fs::remove_dir_all(&path)
+            std::fs::remove_dir_all(path)
                 .with_context(|| format!("Failed to remove directory {}", path.display()))?;
         } else {
             log::warn!("Failed to remove {}", path.display())
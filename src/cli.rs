@@ -4,9 +4,20 @@ pub mod arg {
     pub static PROJECT_DIR: &str = "project_dir";
     pub static VERBOSITY: &str = "verbosity";
     pub static WATCH: &str = "watch";
+    pub static RESTART_BUILDS: &str = "restart_builds";
     pub static CLEAN: &str = "clean";
+    pub static JOBS: &str = "jobs";
+    pub static KEEP_GOING: &str = "keep_going";
+    pub static EXPLAIN: &str = "explain";
+    pub static DRY_RUN: &str = "dry_run";
+    pub static NO_OUTPUT_PREFIX: &str = "no_output_prefix";
+    pub static MESSAGE_FORMAT: &str = "message_format";
+    pub static MESSAGE_FILE: &str = "message_file";
+    pub static LIST: &str = "list";
+    pub static ALL: &str = "all";
     pub static GENERATE_ZSH_COMPLETION: &str = "generate_zsh_completion";
     pub static TARGETS: &str = "targets";
+    pub static ARGS: &str = "args";
 }
 
 pub fn get_app() -> App<'static> {
@@ -34,11 +45,78 @@ pub fn get_app() -> App<'static> {
         .arg(Arg::with_name(arg::WATCH).short('w').long("watch").about(
             "Enable watch mode: rebuild targets and restart services on file system changes",
         ))
+        .arg(
+            Arg::with_name(arg::RESTART_BUILDS)
+                .long("restart-builds")
+                .requires(arg::WATCH)
+                .about("In watch mode, cancel the builds whose inputs change while they run, and start them over"),
+        )
         .arg(
             Arg::with_name(arg::CLEAN)
                 .long("clean")
                 .about("Start by cleaning the target outputs"),
         )
+        .arg(
+            Arg::with_name(arg::JOBS)
+                .short('j')
+                .long("jobs")
+                .takes_value(true)
+                .value_name("JOBS")
+                .validator(is_valid_jobs)
+                .about("Maximum number of build commands to run in parallel (defaults to the number of CPUs)"),
+        )
+        .arg(
+            Arg::with_name(arg::KEEP_GOING)
+                .short('k')
+                .long("keep-going")
+                .conflicts_with(arg::WATCH)
+                .about("Keep building the targets which do not depend on a failed target"),
+        )
+        .arg(
+            Arg::with_name(arg::EXPLAIN)
+                .long("explain")
+                .about("Explain why targets are built instead of being skipped"),
+        )
+        .arg(
+            Arg::with_name(arg::DRY_RUN)
+                .long("dry-run")
+                .conflicts_with_all(&[arg::WATCH, arg::CLEAN])
+                .about("List the targets which would be built or skipped, without running anything"),
+        )
+        .arg(
+            Arg::with_name(arg::NO_OUTPUT_PREFIX)
+                .long("no-output-prefix")
+                .about("Do not prefix the output of the commands with the name of their target"),
+        )
+        .arg(
+            Arg::with_name(arg::MESSAGE_FORMAT)
+                .long("message-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["human", "json"])
+                .default_value("human")
+                .about("Format of the build messages (json emits a stream of JSON build events)"),
+        )
+        .arg(
+            Arg::with_name(arg::MESSAGE_FILE)
+                .long("message-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .about("File to write the JSON build events to, instead of stdout (implies --message-format json)"),
+        )
+        .arg(
+            Arg::with_name(arg::LIST)
+                .short('l')
+                .long("list")
+                .conflicts_with_all(&[arg::WATCH, arg::CLEAN, arg::DRY_RUN])
+                .about("List the targets of the project, with their descriptions and dependencies"),
+        )
+        .arg(
+            Arg::with_name(arg::ALL)
+                .long("all")
+                .requires(arg::LIST)
+                .about("Also list the internal targets (whose names start with '_')"),
+        )
         .arg(
             Arg::with_name(arg::GENERATE_ZSH_COMPLETION)
                 .long("generate-zsh-completion")
@@ -48,11 +126,25 @@ pub fn get_app() -> App<'static> {
             Arg::with_name(arg::TARGETS)
                 .value_name("TARGETS")
                 .multiple(true)
-                .about("Targets to build"),
+                .about("Targets to build (defaults to the default targets of the project)"),
+        )
+        .arg(
+            Arg::with_name(arg::ARGS)
+                .value_name("ARGS")
+                .multiple(true)
+                .last(true)
+                .about("Arguments forwarded to the commands of the requested target, in place of {{args}}"),
         )
         .setting(AppSettings::ColoredHelp)
 }
 
+fn is_valid_jobs(value: &str) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(()),
+        _ => Err(format!("{} is not a strictly positive integer", value)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{arg, get_app};
@@ -78,4 +170,17 @@ mod tests {
         let arg_matches = get_app().get_matches_from(vec!["zinoma", "-vvv"]);
         assert_eq!(arg_matches.occurrences_of(arg::VERBOSITY), 3);
     }
+
+    #[test]
+    fn test_get_app_jobs_accepts_positive_integer() {
+        let arg_matches = get_app().get_matches_from(vec!["zinoma", "-j", "4", "check"]);
+        assert_eq!(arg_matches.value_of(arg::JOBS), Some("4"));
+    }
+
+    #[test]
+    fn test_get_app_jobs_rejects_zero() {
+        get_app()
+            .try_get_matches_from(vec!["zinoma", "--jobs", "0", "check"])
+            .expect_err("Zero jobs should be rejected");
+    }
 }
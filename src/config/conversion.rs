@@ -1,23 +1,52 @@
+use super::env_file::read_env_file;
 use crate::config;
 use crate::domain;
+use crate::paths::{to_absolute_path, IgnoreFiles, Paths};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Debounce window of the watch mode, in milliseconds, unless configured otherwise.
+const DEFAULT_WATCH_DEBOUNCE: u64 = 100;
+
+/// Project-level settings, applying to all targets.
+#[derive(Default)]
+pub struct ProjectSettings {
+    pub use_ignore_files: bool,
+    pub env: HashMap<String, String>,
+    pub env_clear: bool,
+    /// Debounce window of the watch mode, in milliseconds.
+    pub watch_debounce: Option<u64>,
+}
 
 pub fn into_targets(
     mut parsed_targets: HashMap<String, config::Target>,
     project_dir: &Path,
     requested_targets: &Option<Vec<String>>,
+    project_settings: &ProjectSettings,
+    args: &[String],
 ) -> Result<Vec<domain::Target>> {
     let all_target_names: Vec<_> = parsed_targets.keys().cloned().collect();
     let requested_targets = requested_targets.as_ref().unwrap_or(&all_target_names);
     let mut targets = Vec::with_capacity(requested_targets.len());
     let mut mapping = HashMap::with_capacity(requested_targets.len());
+    let current_dir = env::current_dir().with_context(|| "Failed to get current directory")?;
+    let project_dir = &to_absolute_path(project_dir, &current_dir);
+    let ignore_files = if project_settings.use_ignore_files {
+        Some(Arc::new(IgnoreFiles::new(project_dir)?))
+    } else {
+        None
+    };
 
     fn add_target(
-        mut targets: &mut Vec<domain::Target>,
-        mut mapping: &mut HashMap<String, domain::TargetId>,
+        targets: &mut Vec<domain::Target>,
+        mapping: &mut HashMap<String, domain::TargetId>,
         project_dir: &Path,
+        project_settings: &ProjectSettings,
+        ignore_files: &Option<Arc<IgnoreFiles>>,
         parsed_targets: &mut HashMap<String, config::Target>,
         target_name: &str,
     ) -> Result<()> {
@@ -31,14 +60,25 @@ pub fn into_targets(
             output_paths,
             build_list,
             service,
+            track_dependency_outputs,
+            env,
+            env_file,
+            env_clear,
+            input_env,
+            input_commands,
+            dir,
+            description: _,
+            watch_debounce,
         } = parsed_targets
             .remove(target_name)
             .with_context(|| format!("Target {} does not exist", target_name))?;
         for dependency in &dependencies {
             add_target(
-                &mut targets,
-                &mut mapping,
+                targets,
+                mapping,
                 project_dir,
+                project_settings,
+                ignore_files,
                 parsed_targets,
                 dependency,
             )?
@@ -46,27 +86,54 @@ pub fn into_targets(
 
         let target_id = targets.len();
         mapping.insert(target_name.to_string(), target_id);
-        let dependencies = dependencies
+        let dependencies: Vec<_> = dependencies
             .into_iter()
             .map(|target_name| *mapping.get(&target_name).unwrap())
             .collect();
-        let input_paths = input_paths
-            .into_iter()
-            .map(|path| project_dir.join(path))
-            .collect();
-        let output_paths = output_paths
-            .into_iter()
-            .map(|path| project_dir.join(path))
-            .collect();
+        let target_dir = match dir {
+            Some(dir) => get_target_dir(project_dir, &dir)
+                .with_context(|| format!("Invalid dir for target {}", target_name))?,
+            None => project_dir.to_path_buf(),
+        };
+        let mut input_paths = Paths::new(&target_dir, &input_paths)
+            .with_context(|| format!("Invalid input_paths for target {}", target_name))?;
+        if let Some(ignore_files) = ignore_files {
+            input_paths = input_paths.with_ignore_files(ignore_files.clone());
+        }
+        if track_dependency_outputs {
+            for paths in get_transitive_dependency_output_paths(targets, &dependencies) {
+                input_paths.extend(paths);
+            }
+        }
+        let output_paths = Paths::new(&target_dir, &output_paths)
+            .with_context(|| format!("Invalid output_paths for target {}", target_name))?;
+        let mut target_env: BTreeMap<_, _> = project_settings.env.clone().into_iter().collect();
+        if let Some(env_file) = env_file {
+            target_env.extend(
+                read_env_file(&target_dir.join(env_file))
+                    .with_context(|| format!("Invalid env_file for target {}", target_name))?,
+            );
+        }
+        target_env.extend(env);
         targets.push(domain::Target {
             id: target_id,
             name: target_name.to_string(),
             dependencies,
-            path: project_dir.to_path_buf(),
+            project_dir: project_dir.to_path_buf(),
+            path: target_dir,
             input_paths,
             output_paths,
             build_list,
             service,
+            env: target_env,
+            env_clear: env_clear.unwrap_or(project_settings.env_clear),
+            input_env,
+            input_commands,
+            watch_debounce: Duration::from_millis(
+                watch_debounce
+                    .or(project_settings.watch_debounce)
+                    .unwrap_or(DEFAULT_WATCH_DEBOUNCE),
+            ),
         });
 
         Ok(())
@@ -77,19 +144,149 @@ pub fn into_targets(
             &mut targets,
             &mut mapping,
             project_dir,
+            project_settings,
+            &ignore_files,
             &mut parsed_targets,
             requested_target,
         )?;
     }
 
+    let requested_target_ids: Vec<_> = requested_targets
+        .iter()
+        .map(|target_name| mapping[target_name])
+        .collect();
+    forward_args(&mut targets, &requested_target_ids, args)?;
+
     Ok(targets)
 }
 
+/// Placeholder replaced with the arguments forwarded to the commands of the requested targets.
+const ARGS_PLACEHOLDER: &str = "{{args}}";
+
+/// Replaces the args placeholder in the commands of the requested targets with the forwarded arguments,
+/// and in the commands of the other targets with nothing.
+fn forward_args(
+    targets: &mut [domain::Target],
+    requested_target_ids: &[domain::TargetId],
+    args: &[String],
+) -> Result<()> {
+    let uses_args = |target: &domain::Target| {
+        target
+            .build_list
+            .iter()
+            .chain(target.service.iter())
+            .any(|command| command.contains(ARGS_PLACEHOLDER))
+    };
+
+    if !args.is_empty() {
+        let targets_using_args: Vec<_> = requested_target_ids
+            .iter()
+            .map(|&target_id| &targets[target_id])
+            .filter(|&target| uses_args(target))
+            .map(|target| target.name.as_str())
+            .collect();
+        if targets_using_args.is_empty() {
+            let requested_target_names: Vec<_> = requested_target_ids
+                .iter()
+                .map(|&target_id| targets[target_id].name.as_str())
+                .collect();
+            return Err(anyhow::anyhow!(
+                "Arguments cannot be forwarded, as none of the requested targets use {}: {}",
+                ARGS_PLACEHOLDER,
+                requested_target_names.join(", ")
+            ));
+        }
+        if targets_using_args.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "Arguments can only be forwarded to a single target, but several requested targets use {}: {}",
+                ARGS_PLACEHOLDER,
+                targets_using_args.join(", ")
+            ));
+        }
+    }
+
+    let quoted_args: Vec<_> = args.iter().map(|arg| quote_shell_arg(arg)).collect();
+    let quoted_args = quoted_args.join(" ");
+    for target in targets.iter_mut() {
+        let replacement = if requested_target_ids.contains(&target.id) {
+            quoted_args.as_str()
+        } else {
+            ""
+        };
+        for command in target
+            .build_list
+            .iter_mut()
+            .chain(target.service.iter_mut())
+        {
+            *command = command.replace(ARGS_PLACEHOLDER, replacement);
+        }
+    }
+
+    Ok(())
+}
+
+/// Quotes the argument for `/bin/sh`, unless it only contains safe characters.
+fn quote_shell_arg(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_=+.,/:@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Resolves the directory of a target, ensuring it is a directory of the project (even through symlinks).
+fn get_target_dir(project_dir: &Path, dir: &str) -> Result<PathBuf> {
+    let target_dir = project_dir.join(dir);
+    let canonical_target_dir = target_dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve directory {}", target_dir.display()))?;
+    let canonical_project_dir = project_dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve directory {}", project_dir.display()))?;
+
+    if !canonical_target_dir.is_dir() {
+        return Err(anyhow::anyhow!(
+            "{} is not a directory",
+            target_dir.display()
+        ));
+    }
+    if !canonical_target_dir.starts_with(&canonical_project_dir) {
+        return Err(anyhow::anyhow!(
+            "{} is outside of the project directory",
+            target_dir.display()
+        ));
+    }
+
+    Ok(target_dir)
+}
+
+/// Lists the output paths of the provided dependencies, as well as of their own dependencies.
+fn get_transitive_dependency_output_paths<'a>(
+    targets: &'a [domain::Target],
+    dependencies: &[domain::TargetId],
+) -> Vec<&'a Paths> {
+    let mut visited = HashSet::new();
+    let mut to_visit = dependencies.to_vec();
+    let mut output_paths = Vec::new();
+
+    while let Some(target_id) = to_visit.pop() {
+        if visited.insert(target_id) {
+            let target = &targets[target_id];
+            output_paths.push(&target.output_paths);
+            to_visit.extend(&target.dependencies);
+        }
+    }
+
+    output_paths
+}
+
 #[cfg(test)]
 mod tests {
-    use super::into_targets;
+    use super::{into_targets, quote_shell_arg, ProjectSettings};
     use crate::config::tests::build_targets;
     use crate::config::Target;
+    use std::collections::HashMap;
     use std::path::Path;
 
     #[test]
@@ -99,9 +296,14 @@ mod tests {
             ("target_2", build_target()),
         ]);
 
-        let actual_targets =
-            into_targets(targets, Path::new("."), &Some(vec!["target_2".to_string()]))
-                .expect("Conversion of valid targets should be successful");
+        let actual_targets = into_targets(
+            targets,
+            Path::new("."),
+            &Some(vec!["target_2".to_string()]),
+            &ProjectSettings::default(),
+            &[],
+        )
+        .expect("Conversion of valid targets should be successful");
 
         assert_eq!(actual_targets.len(), 1);
         assert_eq!(actual_targets[0].name, "target_2");
@@ -115,10 +317,139 @@ mod tests {
             targets,
             Path::new("."),
             &Some(vec!["not_a_target".to_string()]),
+            &ProjectSettings::default(),
+            &[],
         )
         .expect_err("Should reject an invalid requested target");
     }
 
+    #[test]
+    fn test_into_targets_should_track_transitive_dependency_outputs() {
+        let targets = build_targets(vec![
+            (
+                "codegen",
+                Target {
+                    output_paths: vec!["generated".to_string()],
+                    ..build_target()
+                },
+            ),
+            (
+                "aggregate",
+                Target {
+                    dependencies: vec!["codegen".to_string()],
+                    ..build_target()
+                },
+            ),
+            (
+                "compile",
+                Target {
+                    dependencies: vec!["aggregate".to_string()],
+                    input_paths: vec!["src".to_string()],
+                    track_dependency_outputs: true,
+                    ..build_target()
+                },
+            ),
+        ]);
+
+        let actual_targets = into_targets(
+            targets,
+            Path::new("."),
+            &Some(vec!["compile".to_string()]),
+            &ProjectSettings::default(),
+            &[],
+        )
+        .expect("Conversion of valid targets should be successful");
+
+        let compile = actual_targets
+            .iter()
+            .find(|target| target.name == "compile")
+            .unwrap();
+        let current_dir = std::env::current_dir().unwrap();
+        assert_eq!(
+            compile.input_paths.get_roots(),
+            vec![current_dir.join("src"), current_dir.join("generated")]
+        );
+    }
+
+    #[test]
+    fn test_into_targets_should_forward_args_to_requested_targets() {
+        let targets = build_targets(vec![
+            (
+                "lint",
+                Target {
+                    build_list: vec!["eslint {{args}}".to_string()],
+                    ..build_target()
+                },
+            ),
+            (
+                "test",
+                Target {
+                    dependencies: vec!["lint".to_string()],
+                    build_list: vec!["jest {{args}}".to_string()],
+                    ..build_target()
+                },
+            ),
+        ]);
+
+        let actual_targets = into_targets(
+            targets,
+            Path::new("."),
+            &Some(vec!["test".to_string()]),
+            &ProjectSettings::default(),
+            &["--watch".to_string(), "my test".to_string()],
+        )
+        .expect("Conversion of valid targets should be successful");
+
+        assert_eq!(actual_targets[0].build_list, vec!["eslint "]);
+        assert_eq!(actual_targets[1].build_list, vec!["jest --watch 'my test'"]);
+    }
+
+    #[test]
+    fn test_into_targets_should_reject_args_for_several_targets() {
+        let target = || Target {
+            build_list: vec!["echo {{args}}".to_string()],
+            ..build_target()
+        };
+        let targets = build_targets(vec![("target_1", target()), ("target_2", target())]);
+
+        into_targets(
+            targets,
+            Path::new("."),
+            &Some(vec!["target_1".to_string(), "target_2".to_string()]),
+            &ProjectSettings::default(),
+            &["--fix".to_string()],
+        )
+        .expect_err("Should reject args forwarded to several targets");
+    }
+
+    #[test]
+    fn test_into_targets_should_reject_args_unused_by_requested_targets() {
+        let targets = build_targets(vec![(
+            "target_1",
+            Target {
+                build_list: vec!["echo".to_string()],
+                ..build_target()
+            },
+        )]);
+
+        into_targets(
+            targets,
+            Path::new("."),
+            &Some(vec!["target_1".to_string()]),
+            &ProjectSettings::default(),
+            &["--fix".to_string()],
+        )
+        .expect_err("Should reject args which would not be forwarded");
+    }
+
+    #[test]
+    fn test_quote_shell_arg() {
+        assert_eq!(quote_shell_arg("--fix"), "--fix");
+        assert_eq!(quote_shell_arg("a b"), "'a b'");
+        assert_eq!(quote_shell_arg("it's"), "'it'\\''s'");
+        assert_eq!(quote_shell_arg(""), "''");
+    }
+
     fn build_target() -> Target {
         Target {
             dependencies: vec![],
@@ -126,6 +457,15 @@ mod tests {
             output_paths: vec![],
             build_list: vec![],
             service: None,
+            track_dependency_outputs: false,
+            env: HashMap::new(),
+            env_file: None,
+            env_clear: None,
+            input_env: vec![],
+            input_commands: vec![],
+            dir: None,
+            description: None,
+            watch_debounce: None,
         }
     }
 }
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Reads the environment variables defined in a dotenv file.
+pub fn read_env_file(file: &Path) -> Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(file)
+        .with_context(|| format!("Something went wrong reading {}", file.display()))?;
+    parse_env_file(&contents).with_context(|| format!("Invalid format for {}", file.display()))
+}
+
+/// Parses the `KEY=value` lines of a dotenv file.
+///
+/// Empty lines and lines starting with `#` are skipped, and a leading `export` keyword is allowed.
+/// Values can be surrounded by single or double quotes.
+fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.find('=') {
+            Some(position) => (line[..position].trim(), line[position + 1..].trim()),
+            None => {
+                return Err(anyhow::anyhow!(
+                    "Line {}: expected KEY=value, found {}",
+                    index + 1,
+                    line
+                ))
+            }
+        };
+        if key.is_empty() {
+            return Err(anyhow::anyhow!("Line {}: empty variable name", index + 1));
+        }
+
+        variables.push((key.to_string(), unquote(value).to_string()));
+    }
+
+    Ok(variables)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in &['"', '\''] {
+        if value.len() >= 2 && value.starts_with(*quote) && value.ends_with(*quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_env_file;
+
+    #[test]
+    fn test_parse_env_file() {
+        let contents =
+            "# Comment\n\nFOO=bar\nexport EMPTY=\nQUOTED = \"hello world\"\nSINGLE='a=b'\n";
+
+        let variables = parse_env_file(contents).expect("The env file should be valid");
+
+        assert_eq!(
+            variables,
+            vec![
+                ("FOO".to_string(), "bar".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+                ("QUOTED".to_string(), "hello world".to_string()),
+                ("SINGLE".to_string(), "a=b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_env_file_should_reject_invalid_line() {
+        parse_env_file("FOO=bar\nBAZ\n").expect_err("Should reject a line without =");
+    }
+}
@@ -1,13 +1,19 @@
 mod conversion;
+mod env_file;
 mod validation;
 
 use crate::domain;
 use anyhow::{Context, Result};
+use conversion::ProjectSettings;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use validation::validate_targets;
+use std::path::{Path, PathBuf};
+use validation::{validate_default_targets, validate_jobs, validate_targets, validate_version};
+
+/// Latest version of the configuration format. Some defaults depend on the version of the configuration,
+/// so that the configurations written for older versions keep their behavior.
+pub const LATEST_VERSION: u32 = 2;
 
 #[derive(Debug, Deserialize)]
 pub struct Target {
@@ -21,28 +27,102 @@ pub struct Target {
     build_list: Vec<String>,
     #[serde(default)]
     service: Option<String>,
+    #[serde(default)]
+    track_dependency_outputs: bool,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    env_file: Option<String>,
+    #[serde(default)]
+    env_clear: Option<bool>,
+    #[serde(default)]
+    input_env: Vec<String>,
+    #[serde(default)]
+    input_commands: Vec<String>,
+    #[serde(default)]
+    dir: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    watch_debounce: Option<u64>,
+}
+
+impl Target {
+    pub fn get_dependencies(&self) -> &[String] {
+        &self.dependencies
+    }
+
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    version: Option<u32>,
     #[serde(default)]
     targets: HashMap<String, Target>,
+    #[serde(default)]
+    default: Vec<String>,
+    #[serde(default)]
+    jobs: Option<usize>,
+    #[serde(default)]
+    cache: CacheConfig,
+    #[serde(default)]
+    use_ignore_files: Option<bool>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    env_clear: bool,
+    #[serde(default)]
+    watch_debounce: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CacheConfig {
+    /// Whether target outputs are stored in, and restored from, the local cache.
+    #[serde(default)]
+    pub local: bool,
+    /// Remote cache shared between machines, if any.
+    #[serde(default)]
+    pub remote: Option<RemoteCacheConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteCacheConfig {
+    pub url: String,
+    /// One of `read`, `write` or `read_write`. Defaults to `read`.
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Maximum size of an artifact, in bytes.
+    #[serde(default)]
+    pub max_artifact_size: Option<u64>,
 }
 
 impl Config {
+    /// Path of the configuration file of the project.
+    pub fn get_file_path(project_dir: &Path) -> PathBuf {
+        project_dir.join("zinoma.yml")
+    }
+
     pub fn load(project_dir: &Path) -> Result<Self> {
-        let config_file = project_dir.join("zinoma.yml");
+        let config_file = Self::get_file_path(project_dir);
         let contents = fs::read_to_string(&config_file)
             .with_context(|| format!("Something went wrong reading {}", config_file.display()))?;
         let config: Self = serde_yaml::from_str(&contents)
             .with_context(|| format!("Invalid format for {}", config_file.display()))?;
 
-        validate_targets(&config.targets).with_context(|| {
-            format!(
-                "Invalid configuration found in file {}",
-                config_file.display()
-            )
-        })?;
+        validate_version(config.version)
+            .and_then(|_| validate_targets(&config.targets))
+            .and_then(|_| validate_default_targets(&config.default, &config.targets))
+            .and_then(|_| validate_jobs(config.jobs))
+            .with_context(|| {
+                format!(
+                    "Invalid configuration found in file {}",
+                    config_file.display()
+                )
+            })?;
 
         Ok(config)
     }
@@ -54,21 +134,75 @@ impl Config {
             .collect()
     }
 
+    pub fn get_targets(&self) -> &HashMap<String, Target> {
+        &self.targets
+    }
+
+    /// Targets to build when none is requested.
+    pub fn get_default_targets(&self) -> &[String] {
+        &self.default
+    }
+
+    pub fn get_jobs(&self) -> Option<usize> {
+        self.jobs
+    }
+
+    pub fn get_cache(&self) -> &CacheConfig {
+        &self.cache
+    }
+
+    /// Configurations without a version are of version 1.
+    fn get_version(&self) -> u32 {
+        self.version.unwrap_or(1)
+    }
+
+    /// Ignore files are respected by default since version 2.
+    fn uses_ignore_files(&self) -> bool {
+        self.use_ignore_files
+            .unwrap_or_else(|| self.get_version() >= 2)
+    }
+
     pub fn into_targets(
         self,
         project_dir: &Path,
         requested_targets: &Option<Vec<String>>,
+        args: &[String],
     ) -> Result<Vec<domain::Target>> {
-        conversion::into_targets(self.targets, project_dir, requested_targets)
+        let project_settings = ProjectSettings {
+            use_ignore_files: self.uses_ignore_files(),
+            env: self.env,
+            env_clear: self.env_clear,
+            watch_debounce: self.watch_debounce,
+        };
+        conversion::into_targets(
+            self.targets,
+            project_dir,
+            requested_targets,
+            &project_settings,
+            args,
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Target;
+    use super::{Config, Target};
     use std::collections::HashMap;
 
     pub fn build_targets(data: Vec<(&str, Target)>) -> HashMap<String, Target> {
         data.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
     }
+
+    fn parse_config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).expect("The configuration should be valid")
+    }
+
+    #[test]
+    fn test_uses_ignore_files_depends_on_the_version() {
+        assert!(!parse_config("targets: {}").uses_ignore_files());
+        assert!(!parse_config("version: 1").uses_ignore_files());
+        assert!(parse_config("version: 2").uses_ignore_files());
+        assert!(!parse_config("version: 2\nuse_ignore_files: false").uses_ignore_files());
+        assert!(parse_config("version: 1\nuse_ignore_files: true").uses_ignore_files());
+    }
 }
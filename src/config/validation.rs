@@ -1,8 +1,9 @@
-use super::Target;
+use super::{Target, LATEST_VERSION};
 use anyhow::{Context, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::collections::HashMap;
+use std::path::{Component, Path};
 
 pub fn validate_targets(targets: &HashMap<String, Target>) -> Result<()> {
     for (target_name, target) in targets.iter() {
@@ -15,6 +16,66 @@ pub fn validate_targets(targets: &HashMap<String, Target>) -> Result<()> {
 
         validate_target(target_name, target, &[], targets)
             .with_context(|| format!("Target {} is invalid", target_name))?;
+
+        if let Some(dir) = &target.dir {
+            validate_target_dir(dir)
+                .with_context(|| format!("Target {} has an invalid dir", target_name))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_default_targets(
+    default_targets: &[String],
+    targets: &HashMap<String, Target>,
+) -> Result<()> {
+    for target_name in default_targets {
+        if !targets.contains_key(target_name) {
+            return Err(anyhow::anyhow!("Default target {} not found", target_name));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_version(version: Option<u32>) -> Result<()> {
+    match version {
+        Some(version) if version == 0 || version > LATEST_VERSION => Err(anyhow::anyhow!(
+            "version {} is not supported (the latest version is {})",
+            version,
+            LATEST_VERSION
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn validate_jobs(jobs: Option<usize>) -> Result<()> {
+    if jobs == Some(0) {
+        return Err(anyhow::anyhow!("jobs must be greater than 0"));
+    }
+
+    Ok(())
+}
+
+/// Ensures that the target directory is relative, and does not lead out of the project directory.
+fn validate_target_dir(dir: &str) -> Result<()> {
+    let mut depth = 0;
+    for component in Path::new(dir).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                return Err(anyhow::anyhow!("{} should be a relative path", dir));
+            }
+            Component::CurDir => {}
+            Component::ParentDir if depth == 0 => {
+                return Err(anyhow::anyhow!(
+                    "{} is outside of the project directory",
+                    dir
+                ));
+            }
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+        }
     }
 
     Ok(())
@@ -51,7 +112,7 @@ fn validate_target(
             anyhow::anyhow!("{} - Dependency {} not found", target_name, dependency_name)
         })?;
 
-        validate_target(dependency_name, dependency, &targets_chain, &targets)?;
+        validate_target(dependency_name, dependency, &targets_chain, targets)?;
     }
 
     Ok(())
@@ -60,9 +121,13 @@ fn validate_target(
 #[cfg(test)]
 mod tests {
     use super::is_valid_target_name;
-    use super::validate_targets;
+    use super::{
+        validate_default_targets, validate_jobs, validate_target_dir, validate_targets,
+        validate_version,
+    };
     use crate::config::tests::build_targets;
     use crate::config::Target;
+    use std::collections::HashMap;
 
     #[test]
     fn test_validate_targets_on_valid_targets() {
@@ -95,6 +160,41 @@ mod tests {
         validate_targets(&targets).expect_err("Circular dependencies should be rejected");
     }
 
+    #[test]
+    fn test_validate_default_targets() {
+        let targets = build_targets(vec![("target_1", build_target_with_dependencies(vec![]))]);
+
+        validate_default_targets(&["target_1".to_string()], &targets)
+            .expect("Existing default targets should be valid");
+        validate_default_targets(&["not_a_target".to_string()], &targets)
+            .expect_err("Should reject an unknown default target");
+    }
+
+    #[test]
+    fn test_validate_version() {
+        validate_version(None).expect("Unspecified version should be accepted");
+        validate_version(Some(1)).expect("Version 1 should be accepted");
+        validate_version(Some(2)).expect("Version 2 should be accepted");
+        validate_version(Some(0)).expect_err("Version 0 should be rejected");
+        validate_version(Some(3)).expect_err("Future versions should be rejected");
+    }
+
+    #[test]
+    fn test_validate_jobs() {
+        validate_jobs(None).expect("Unspecified jobs should be accepted");
+        validate_jobs(Some(2)).expect("Positive jobs should be accepted");
+        validate_jobs(Some(0)).expect_err("Zero jobs should be rejected");
+    }
+
+    #[test]
+    fn test_validate_target_dir() {
+        validate_target_dir("frontend").expect("A subdirectory should be valid");
+        validate_target_dir("./packages/../frontend").expect("A subdirectory should be valid");
+        validate_target_dir("/frontend").expect_err("An absolute path should be rejected");
+        validate_target_dir("../frontend").expect_err("A parent directory should be rejected");
+        validate_target_dir("frontend/../..").expect_err("A parent directory should be rejected");
+    }
+
     #[test]
     fn test_is_valid_target_name() {
         assert!(
@@ -124,6 +224,15 @@ mod tests {
             output_paths: vec![],
             build_list: vec![],
             service: None,
+            track_dependency_outputs: false,
+            env: HashMap::new(),
+            env_file: None,
+            env_clear: None,
+            input_env: vec![],
+            input_commands: vec![],
+            dir: None,
+            description: None,
+            watch_debounce: None,
         }
     }
 }
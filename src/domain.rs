@@ -1,4 +1,7 @@
+use crate::paths::Paths;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub type TargetId = usize;
 
@@ -7,9 +10,45 @@ pub struct Target {
     pub id: TargetId,
     pub name: String,
     pub dependencies: Vec<TargetId>,
+    /// Absolute directory of the project. The paths of the target are located in this directory.
+    pub project_dir: PathBuf,
     pub path: PathBuf,
-    pub input_paths: Vec<PathBuf>,
-    pub output_paths: Vec<PathBuf>,
+    pub input_paths: Paths,
+    pub output_paths: Paths,
     pub build_list: Vec<String>,
     pub service: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub env_clear: bool,
+    pub input_env: Vec<String>,
+    pub input_commands: Vec<String>,
+    /// Window during which the file system events invalidating the target are merged, in watch mode.
+    pub watch_debounce: Duration,
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Target, TargetId};
+    use crate::paths::Paths;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    pub fn build_target(id: TargetId, dependencies: Vec<TargetId>) -> Target {
+        Target {
+            id,
+            name: format!("target_{}", id),
+            dependencies,
+            project_dir: PathBuf::new(),
+            path: PathBuf::new(),
+            input_paths: Paths::default(),
+            output_paths: Paths::default(),
+            build_list: vec![],
+            service: None,
+            env: BTreeMap::new(),
+            env_clear: false,
+            input_env: vec![],
+            input_commands: vec![],
+            watch_debounce: Duration::default(),
+        }
+    }
 }
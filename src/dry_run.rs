@@ -0,0 +1,44 @@
+use crate::domain::Target;
+use crate::engine::incremental::IncrementalRunner;
+use anyhow::Result;
+
+/// Prints the targets in their execution order, along with whether they would be built or skipped.
+///
+/// Nothing gets executed, and the incremental states are left untouched.
+/// In particular, the `input_commands` are not run: their outputs are assumed unchanged.
+pub fn print_execution_plan(
+    targets: &[Target],
+    incremental_runner: &IncrementalRunner,
+) -> Result<()> {
+    let mut would_run = vec![false; targets.len()];
+
+    // Targets are sorted so that dependencies always come before their dependents.
+    for (index, target) in targets.iter().enumerate() {
+        let input_values = incremental_runner.compute_dry_run_input_values(target)?;
+        let build_reason = incremental_runner.explain(target, &input_values)?;
+        let dependency_would_run = target
+            .dependencies
+            .iter()
+            .any(|&dependency_id| would_run[dependency_id]);
+        would_run[target.id] = build_reason.is_some();
+
+        let plan = match build_reason {
+            Some(build_reason) if incremental_runner.is_cached(target, &input_values)? => {
+                format!("restore from cache: {}", build_reason)
+            }
+            Some(build_reason) => format!("run: {}", build_reason),
+            None if dependency_would_run => {
+                "skip, unless its dependencies modify its inputs".to_string()
+            }
+            None => "skip (not modified)".to_string(),
+        };
+        let assumption = if target.input_commands.is_empty() {
+            ""
+        } else {
+            " (assuming the outputs of its input_commands did not change)"
+        };
+        println!("{}. {} - {}{}", index + 1, target.name, plan, assumption);
+    }
+
+    Ok(())
+}
@@ -1,75 +1,222 @@
-use super::builder::BuildReport;
+use super::builder::{BuildCancellation, BuildReport};
 use super::incremental::IncrementalRunResult;
 use crate::domain::{Target, TargetId};
-use anyhow::{Error, Result};
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use anyhow::Result;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use std::collections::BTreeSet;
 
-pub struct TargetBuildStates<'a> {
-    targets: &'a [Target],
+pub struct TargetBuildStates {
     build_states: Vec<TargetBuildState>,
+    dependents: Vec<Vec<TargetId>>,
+    /// Number of direct dependencies which are not yet available to each target.
+    /// A dependency is available once it is built and all its own dependencies are available.
+    pending_dependencies: Vec<usize>,
+    ready_to_build: BTreeSet<TargetId>,
+    running_builds_count: usize,
     pub tx: Sender<BuildReport>,
-    rx: Receiver<BuildReport>,
+    pub rx: Receiver<BuildReport>,
 }
 
-impl<'a> TargetBuildStates<'a> {
-    pub fn new(targets: &'a [Target]) -> Self {
+impl TargetBuildStates {
+    pub fn new(targets: &[Target]) -> Self {
         let (tx, rx) = unbounded();
+
+        let mut dependents = vec![Vec::new(); targets.len()];
+        for target in targets.iter() {
+            for &dependency_id in &target.dependencies {
+                dependents[dependency_id].push(target.id);
+            }
+        }
+
+        let pending_dependencies: Vec<_> = targets
+            .iter()
+            .map(|target| target.dependencies.len())
+            .collect();
+
+        let ready_to_build = targets
+            .iter()
+            .filter(|target| pending_dependencies[target.id] == 0)
+            .map(|target| target.id)
+            .collect();
+
         Self {
-            targets,
             build_states: vec![TargetBuildState::new(); targets.len()],
+            dependents,
+            pending_dependencies,
+            ready_to_build,
+            running_builds_count: 0,
             tx,
             rx,
         }
     }
 
-    pub fn set_builds_invalidated(&mut self, target_ids: &[TargetId]) {
-        for &target_id in target_ids {
-            self.build_states[target_id].build_invalidated();
+    /// Builds the states of the reloaded targets.
+    ///
+    /// The targets which did not change keep their previous state, while the others have to be built.
+    /// It must be called while no build is running.
+    pub fn reload(&self, targets: &[Target], previous_target_ids: &[Option<TargetId>]) -> Self {
+        let mut build_states = Self::new(targets);
+        for (target_id, previous_target_id) in previous_target_ids.iter().enumerate() {
+            if let Some(previous_target_id) = *previous_target_id {
+                build_states.build_states[target_id] = TargetBuildState {
+                    blocked_by: None,
+                    ..self.build_states[previous_target_id].clone()
+                };
+            }
+        }
+
+        // Dependencies always come before their dependents.
+        for target in targets {
+            build_states.pending_dependencies[target.id] = target
+                .dependencies
+                .iter()
+                .filter(|&&dependency_id| !build_states.is_available(dependency_id))
+                .count();
+            build_states.refresh_ready_to_build(target.id);
         }
+
+        build_states
+    }
+
+    pub fn set_build_invalidated(&mut self, target_id: TargetId) {
+        self.update_build_state(target_id, TargetBuildState::build_invalidated);
     }
 
     pub fn set_build_started(&mut self, target_id: TargetId) {
-        self.build_states[target_id].build_started();
+        self.running_builds_count += 1;
+        self.update_build_state(target_id, TargetBuildState::build_started);
     }
 
-    pub fn get_ready_to_build_targets(&self) -> Vec<TargetId> {
+    pub fn set_build_finished(&mut self, build_report: &BuildReport) {
+        self.running_builds_count -= 1;
+        if self.build_states[build_report.target_id].cancelled {
+            self.update_build_state(build_report.target_id, TargetBuildState::build_discarded);
+        } else if let IncrementalRunResult::Run(Err(_)) = &build_report.result {
+            self.update_build_state(build_report.target_id, TargetBuildState::build_failed);
+        } else {
+            self.update_build_state(build_report.target_id, TargetBuildState::build_succeeded);
+            if let IncrementalRunResult::Run(_) | IncrementalRunResult::Restored =
+                build_report.result
+            {
+                self.set_dependents_to_rebuild(build_report.target_id);
+            }
+        }
+    }
+
+    /// Marks the direct dependents of a target whose outputs may have changed to be built again.
+    ///
+    /// They will be built once all their dependencies are available, and skipped if their inputs did not change.
+    /// Their own dependents are marked in turn if they are actually rebuilt.
+    fn set_dependents_to_rebuild(&mut self, target_id: TargetId) {
+        for dependent_id in self.dependents[target_id].clone() {
+            self.update_build_state(dependent_id, TargetBuildState::dependency_rebuilt);
+        }
+    }
+
+    /// Cancels the running build of a target, whose result will be discarded.
+    pub fn cancel_build(&mut self, target_id: TargetId) -> Result<()> {
+        let build_state = &mut self.build_states[target_id];
+        if build_state.being_built && !build_state.cancelled {
+            build_state.cancelled = true;
+            build_state.cancellation.cancel()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the last build of the target was cancelled.
+    pub fn is_build_cancelled(&self, target_id: TargetId) -> bool {
+        self.build_states[target_id].cancelled
+    }
+
+    pub fn get_build_cancellation(&self, target_id: TargetId) -> BuildCancellation {
+        self.build_states[target_id].cancellation.clone()
+    }
+
+    /// Marks all the targets depending (directly or transitively) on a failed target as blocked.
+    pub fn set_dependents_blocked(&mut self, failed_target_id: TargetId) {
+        let mut dependent_ids = self.dependents[failed_target_id].clone();
+        while let Some(dependent_id) = dependent_ids.pop() {
+            if self.build_states[dependent_id].blocked_by.is_none() {
+                self.build_states[dependent_id].blocked_by = Some(failed_target_id);
+                self.refresh_ready_to_build(dependent_id);
+                dependent_ids.extend(&self.dependents[dependent_id]);
+            }
+        }
+    }
+
+    /// Returns the blocked targets, along with the failed target that blocks each of them.
+    pub fn get_blocked_targets(&self) -> Vec<(TargetId, TargetId)> {
         self.build_states
             .iter()
             .enumerate()
-            .filter(|(_target_id, build_state)| build_state.to_build && !build_state.being_built)
-            .map(|(target_id, _build_state)| target_id)
-            .filter(|&target_id| self.has_all_dependencies_built(target_id))
+            .filter_map(|(target_id, build_state)| {
+                build_state
+                    .blocked_by
+                    .map(|failed_target_id| (target_id, failed_target_id))
+            })
             .collect()
     }
 
-    fn has_all_dependencies_built(&self, target_id: TargetId) -> bool {
-        let target = &self.targets[target_id];
+    pub fn get_ready_to_build_targets(&self) -> Vec<TargetId> {
+        self.ready_to_build.iter().copied().collect()
+    }
 
-        target.dependencies.iter().all(|&dependency_id| {
-            self.build_states[dependency_id].built && self.has_all_dependencies_built(dependency_id)
-        })
+    pub fn get_running_builds_count(&self) -> usize {
+        self.running_builds_count
     }
 
-    pub fn all_are_built(&self) -> bool {
+    pub fn all_are_done(&self) -> bool {
         self.build_states
             .iter()
-            .all(|build_state| build_state.built)
+            .all(|build_state| build_state.is_done())
     }
 
-    pub fn get_finished_build(&mut self) -> Result<Option<BuildReport>> {
-        match self.rx.try_recv() {
-            Ok(result) => {
-                let target_build_state = &mut self.build_states[result.target_id];
-                if let IncrementalRunResult::Run(Err(_)) = &result.result {
-                    target_build_state.build_failed();
-                } else {
-                    target_build_state.build_succeeded();
-                }
+    fn update_build_state<F>(&mut self, target_id: TargetId, update: F)
+    where
+        F: FnOnce(&mut TargetBuildState),
+    {
+        let was_available = self.is_available(target_id);
+        update(&mut self.build_states[target_id]);
+        self.refresh_ready_to_build(target_id);
+
+        if was_available != self.is_available(target_id) {
+            self.propagate_availability(target_id);
+        }
+    }
+
+    /// Updates the pending dependency counters of the dependents of a target whose availability just changed.
+    fn propagate_availability(&mut self, target_id: TargetId) {
+        let available = self.is_available(target_id);
+
+        for dependent_id in self.dependents[target_id].clone() {
+            let was_available = self.is_available(dependent_id);
+            if available {
+                self.pending_dependencies[dependent_id] -= 1;
+            } else {
+                self.pending_dependencies[dependent_id] += 1;
+            }
+            self.refresh_ready_to_build(dependent_id);
 
-                Ok(Some(result))
+            if was_available != self.is_available(dependent_id) {
+                self.propagate_availability(dependent_id);
             }
-            Err(TryRecvError::Empty) => Ok(None),
-            Err(e) => Err(Error::new(e).context("Crossbeam parallelism failure")),
+        }
+    }
+
+    fn is_available(&self, target_id: TargetId) -> bool {
+        self.build_states[target_id].built && self.pending_dependencies[target_id] == 0
+    }
+
+    fn refresh_ready_to_build(&mut self, target_id: TargetId) {
+        let build_state = &self.build_states[target_id];
+        if build_state.to_build
+            && !build_state.being_built
+            && build_state.blocked_by.is_none()
+            && self.pending_dependencies[target_id] == 0
+        {
+            self.ready_to_build.insert(target_id);
+        } else {
+            self.ready_to_build.remove(&target_id);
         }
     }
 }
@@ -79,6 +226,10 @@ struct TargetBuildState {
     to_build: bool,
     being_built: bool,
     built: bool,
+    failed: bool,
+    blocked_by: Option<TargetId>,
+    cancelled: bool,
+    cancellation: BuildCancellation,
 }
 
 impl TargetBuildState {
@@ -87,27 +238,232 @@ impl TargetBuildState {
             to_build: true,
             being_built: false,
             built: false,
+            failed: false,
+            blocked_by: None,
+            cancelled: false,
+            cancellation: BuildCancellation::default(),
         }
     }
 
+    pub fn is_done(&self) -> bool {
+        self.built || self.failed || self.blocked_by.is_some()
+    }
+
     pub fn build_invalidated(&mut self) {
         self.to_build = true;
         self.built = false;
+        self.failed = false;
+        self.blocked_by = None;
     }
 
     pub fn build_started(&mut self) {
         self.to_build = false;
         self.being_built = true;
         self.built = false;
+        self.cancelled = false;
+        self.cancellation = BuildCancellation::default();
     }
 
     pub fn build_succeeded(&mut self) {
         self.being_built = false;
         self.built = !self.to_build;
+        self.failed = false;
+    }
+
+    pub fn dependency_rebuilt(&mut self) {
+        self.to_build = true;
+        self.built = false;
+    }
+
+    /// Ends a cancelled build, without changing the state of the target.
+    pub fn build_discarded(&mut self) {
+        self.being_built = false;
     }
 
     pub fn build_failed(&mut self) {
         self.being_built = false;
         self.built = false;
+        self.failed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TargetBuildStates;
+    use crate::domain::tests::build_target;
+    use crate::domain::{Target, TargetId};
+    use crate::engine::builder::BuildReport;
+    use crate::engine::incremental::IncrementalRunResult;
+    use std::time::Duration;
+
+    #[test]
+    fn test_only_targets_without_dependencies_are_initially_ready() {
+        let targets = build_targets(vec![vec![], vec![0], vec![1], vec![]]);
+        let build_states = TargetBuildStates::new(&targets);
+
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![0, 3]);
+    }
+
+    #[test]
+    fn test_dependents_become_ready_once_dependencies_are_built() {
+        let targets = build_targets(vec![vec![], vec![], vec![0, 1]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+
+        build_states.set_build_started(0);
+        build_states.set_build_started(1);
+        assert!(build_states.get_ready_to_build_targets().is_empty());
+
+        build_states.set_build_finished(&success(0));
+        assert!(build_states.get_ready_to_build_targets().is_empty());
+
+        build_states.set_build_finished(&success(1));
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![2]);
+
+        build_states.set_build_started(2);
+        build_states.set_build_finished(&success(2));
+        assert!(build_states.all_are_done());
+    }
+
+    #[test]
+    fn test_invalidated_transitive_dependency_blocks_dependents() {
+        let targets = build_targets(vec![vec![], vec![0], vec![1]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+
+        for target_id in 0..2 {
+            build_states.set_build_started(target_id);
+            build_states.set_build_finished(&success(target_id));
+        }
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![2]);
+
+        build_states.set_build_invalidated(0);
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![0]);
+
+        build_states.set_build_started(0);
+        build_states.set_build_finished(&success(0));
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![1]);
+
+        build_states.set_build_started(1);
+        build_states.set_build_finished(&success(1));
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![2]);
+    }
+
+    #[test]
+    fn test_skipped_dependency_does_not_rebuild_dependents() {
+        let targets = build_targets(vec![vec![], vec![0]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+
+        for target_id in 0..2 {
+            build_states.set_build_started(target_id);
+            build_states.set_build_finished(&success(target_id));
+        }
+
+        build_states.set_build_invalidated(0);
+        build_states.set_build_started(0);
+        build_states.set_build_finished(&skipped(0));
+
+        assert!(build_states.get_ready_to_build_targets().is_empty());
+        assert!(build_states.all_are_done());
+    }
+
+    #[test]
+    fn test_cancelled_build_is_discarded() {
+        let targets = build_targets(vec![vec![], vec![0]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+
+        build_states.set_build_started(0);
+        build_states.set_build_invalidated(0);
+        build_states.cancel_build(0).unwrap();
+        assert!(build_states.get_build_cancellation(0).is_cancelled());
+        build_states.set_build_finished(&failure(0));
+
+        assert!(build_states.is_build_cancelled(0));
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![0]);
+
+        build_states.set_build_started(0);
+        assert!(!build_states.is_build_cancelled(0));
+        build_states.set_build_finished(&success(0));
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![1]);
+    }
+
+    #[test]
+    fn test_reload_keeps_the_states_of_unchanged_targets() {
+        let targets = build_targets(vec![vec![], vec![0], vec![1]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+        for target_id in 0..3 {
+            build_states.set_build_started(target_id);
+            build_states.set_build_finished(&success(target_id));
+        }
+
+        let reloaded_targets = build_targets(vec![vec![], vec![0], vec![], vec![2]]);
+        let build_states =
+            build_states.reload(&reloaded_targets, &[Some(0), Some(1), None, Some(2)]);
+
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![2]);
+        assert!(!build_states.all_are_done());
+    }
+
+    #[test]
+    fn test_failed_build_does_not_unblock_dependents() {
+        let targets = build_targets(vec![vec![], vec![0]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+
+        build_states.set_build_started(0);
+        build_states.set_build_finished(&failure(0));
+
+        assert!(build_states.get_ready_to_build_targets().is_empty());
+        assert!(!build_states.all_are_done());
+    }
+
+    #[test]
+    fn test_dependents_of_failed_build_are_blocked() {
+        let targets = build_targets(vec![vec![], vec![0], vec![1], vec![]]);
+        let mut build_states = TargetBuildStates::new(&targets);
+
+        build_states.set_build_started(0);
+        build_states.set_build_finished(&failure(0));
+        build_states.set_dependents_blocked(0);
+
+        assert_eq!(build_states.get_blocked_targets(), vec![(1, 0), (2, 0)]);
+        assert_eq!(build_states.get_ready_to_build_targets(), vec![3]);
+        assert!(!build_states.all_are_done());
+
+        build_states.set_build_started(3);
+        build_states.set_build_finished(&success(3));
+        assert!(build_states.all_are_done());
+    }
+
+    fn success(target_id: TargetId) -> BuildReport {
+        BuildReport::new(
+            target_id,
+            IncrementalRunResult::Run(Ok(())),
+            Duration::default(),
+            vec![],
+        )
+    }
+
+    fn skipped(target_id: TargetId) -> BuildReport {
+        BuildReport::new(
+            target_id,
+            IncrementalRunResult::Skipped,
+            Duration::default(),
+            vec![],
+        )
+    }
+
+    fn failure(target_id: TargetId) -> BuildReport {
+        BuildReport::new(
+            target_id,
+            IncrementalRunResult::Run(Err(anyhow::anyhow!("Build failed"))),
+            Duration::default(),
+            vec![],
+        )
+    }
+
+    fn build_targets(dependencies: Vec<Vec<TargetId>>) -> Vec<Target> {
+        dependencies
+            .into_iter()
+            .enumerate()
+            .map(|(id, dependencies)| build_target(id, dependencies))
+            .collect()
     }
 }
@@ -1,26 +1,65 @@
+use super::command::{
+    build_command, start_command, start_command_in_process_group, CommandKiller, OutputOptions,
+    RunningCommand,
+};
+use super::events::{Event, EventEmitter, SkipReason};
 use super::incremental::{IncrementalRunResult, IncrementalRunner};
 use crate::domain::{Target, TargetId};
 use anyhow::{Context, Result};
 use crossbeam::channel::Sender;
 use crossbeam::thread::Scope;
-use duct::cmd;
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub struct TargetBuilder<'a> {
     incremental_runner: IncrementalRunner<'a>,
+    output_options: OutputOptions,
+    event_emitter: EventEmitter,
 }
 
 impl<'a> TargetBuilder<'a> {
-    pub fn new(incremental_runner: IncrementalRunner<'a>) -> Self {
-        Self { incremental_runner }
+    pub fn new(
+        incremental_runner: IncrementalRunner<'a>,
+        output_options: OutputOptions,
+        event_emitter: EventEmitter,
+    ) -> Self {
+        Self {
+            incremental_runner,
+            output_options,
+            event_emitter,
+        }
     }
 
-    pub fn build(&'a self, scope: &Scope<'a>, target: &'a Target, tx: &Sender<BuildReport>) {
+    /// Builds the target in a new thread.
+    ///
+    /// When the build is `cancellable`, its commands run in their own process group,
+    /// so that cancelling the build also kills the processes they started.
+    pub fn build(
+        &'a self,
+        scope: &Scope<'a>,
+        target: &Target,
+        cancellable: bool,
+        cancellation: BuildCancellation,
+        tx: &Sender<BuildReport>,
+    ) {
+        self.event_emitter.emit(Event::TargetScheduled {
+            target: target.name.to_string(),
+        });
+
         let tx = tx.clone();
+        let target = target.clone();
         scope.spawn(move |_| {
-            build_target(target, &self.incremental_runner, &tx)
-                .with_context(|| format!("Error building target {}", target.id))
-                .unwrap()
+            build_target(
+                &target,
+                &self.incremental_runner,
+                &self.output_options,
+                &self.event_emitter,
+                cancellable,
+                &cancellation,
+                &tx,
+            )
+            .with_context(|| format!("Error building target {}", target.id))
+            .unwrap()
         });
     }
 }
@@ -28,58 +67,193 @@ impl<'a> TargetBuilder<'a> {
 pub fn build_target(
     target: &Target,
     incremental_runner: &IncrementalRunner,
+    output_options: &OutputOptions,
+    event_emitter: &EventEmitter,
+    cancellable: bool,
+    cancellation: &BuildCancellation,
     tx: &Sender<BuildReport>,
 ) -> Result<()> {
-    let result = incremental_runner
-        .run(&target, || {
-            let target_start = Instant::now();
-            log::info!("{} - Building", &target.name);
-            for command in &target.build_list {
-                let command_start = Instant::now();
-                log::debug!("{} - Command \"{}\" - Executing", target.name, command);
-                let command_output = cmd!("/bin/sh", "-c", command)
-                    .dir(&target.path)
-                    .stderr_to_stdout()
-                    .run()
-                    .with_context(|| "Command execution error")?;
-                print!(
-                    "{}",
-                    String::from_utf8(command_output.stdout)
-                        .with_context(|| "Failed to interpret stdout as utf-8")?
-                );
-                let command_execution_duration = command_start.elapsed();
-                log::debug!(
-                    "{} - Command \"{}\" - Success (took: {}ms)",
-                    target.name,
+    let output_prefix = output_options.get_prefix(target);
+    let target_start = Instant::now();
+    let mut command_reports = Vec::with_capacity(target.build_list.len());
+    let is_cancelled = || cancellation.is_cancelled();
+    let run_result = incremental_runner.run(target, is_cancelled, || {
+        log::info!("{} - Building", &target.name);
+        event_emitter.emit(Event::TargetStarted {
+            target: target.name.to_string(),
+        });
+        for command in &target.build_list {
+            if cancellation.is_cancelled() {
+                return Err(anyhow::anyhow!("Build cancelled"));
+            }
+
+            let command_start = Instant::now();
+            log::debug!("{} - Command \"{}\" - Executing", target.name, command);
+            event_emitter.emit(Event::CommandStarted {
+                target: target.name.to_string(),
+                command: command.to_string(),
+            });
+            let start = if cancellable {
+                start_command_in_process_group
+            } else {
+                start_command
+            };
+            let command_result = start(
+                &build_command(target, command),
+                output_options,
+                &output_prefix,
+            )
+            .and_then(|running_command| {
+                cancellation.set_running_command(Some(&running_command))?;
+                let exit_status = running_command.wait();
+                cancellation.set_running_command(None)?;
+                exit_status
+            });
+            let command_execution_duration = command_start.elapsed();
+            if cancellation.is_cancelled() {
+                return Err(anyhow::anyhow!("Build cancelled"));
+            }
+
+            let exit_status = command_result.as_ref().ok();
+            event_emitter.emit(Event::CommandFinished {
+                target: target.name.to_string(),
+                command: command.to_string(),
+                exit_code: exit_status.and_then(|status| status.code()),
+                duration_ms: command_execution_duration.as_millis(),
+            });
+            command_reports.push(CommandReport {
+                command: command.to_string(),
+                duration: command_execution_duration,
+                succeeded: matches!(exit_status, Some(status) if status.success()),
+            });
+
+            let exit_status = command_result.with_context(|| "Command execution error")?;
+            if !exit_status.success() {
+                return Err(anyhow::anyhow!(
+                    "Command \"{}\" failed ({})",
                     command,
-                    command_execution_duration.as_millis()
-                );
+                    exit_status
+                ));
             }
-            let target_build_duration = target_start.elapsed();
-            log::info!(
-                "{} - Built (took: {}ms)",
+            log::debug!(
+                "{} - Command \"{}\" - Success (took: {}ms)",
                 target.name,
-                target_build_duration.as_millis()
+                command,
+                command_execution_duration.as_millis()
             );
-            Ok(())
-        })
-        .with_context(|| "Incremental build error")?;
+        }
+        log::info!(
+            "{} - Built (took: {}ms)",
+            target.name,
+            target_start.elapsed().as_millis()
+        );
+        Ok(())
+    });
+    // Failing to check or save the incremental state of the target fails its build.
+    let result = run_result
+        .unwrap_or_else(|e| IncrementalRunResult::Run(Err(e.context("Incremental build error"))));
 
-    if let IncrementalRunResult::Skipped = result {
-        log::info!("{} - Build skipped (Not Modified)", target.name);
+    let target_build_duration = target_start.elapsed();
+    match &result {
+        // The result of a cancelled build is outdated, and therefore not reported.
+        _ if cancellation.is_cancelled() => log::info!("{} - Build cancelled", target.name),
+        IncrementalRunResult::Skipped => {
+            log::info!("{} - Build skipped (Not Modified)", target.name);
+            event_emitter.emit(Event::TargetSkipped {
+                target: target.name.to_string(),
+                reason: SkipReason::NotModified,
+            });
+        }
+        IncrementalRunResult::Restored => {
+            log::info!("{} - Build skipped (Restored from cache)", target.name);
+            event_emitter.emit(Event::TargetSkipped {
+                target: target.name.to_string(),
+                reason: SkipReason::RestoredFromCache,
+            });
+        }
+        IncrementalRunResult::Run(Ok(_)) => event_emitter.emit(Event::TargetFinished {
+            target: target.name.to_string(),
+            duration_ms: target_build_duration.as_millis(),
+        }),
+        IncrementalRunResult::Run(Err(e)) => event_emitter.emit(Event::TargetFailed {
+            target: target.name.to_string(),
+            error: format!("{:#}", e),
+            duration_ms: target_build_duration.as_millis(),
+        }),
     }
 
-    tx.send(BuildReport::new(target.id, result))
-        .with_context(|| "Sender error")
+    // The receiver is dropped once the build is over (e.g. interrupted in watch mode): the report is then not needed anymore.
+    let build_report = BuildReport::new(target.id, result, target_build_duration, command_reports);
+    if tx.send(build_report).is_err() {
+        log::debug!("{} - Build report discarded", target.name);
+    }
+    Ok(())
 }
 
 pub struct BuildReport {
     pub target_id: TargetId,
     pub result: IncrementalRunResult<Result<()>>,
+    pub duration: Duration,
+    pub commands: Vec<CommandReport>,
 }
 
 impl BuildReport {
-    pub fn new(target_id: TargetId, result: IncrementalRunResult<Result<()>>) -> Self {
-        Self { target_id, result }
+    pub fn new(
+        target_id: TargetId,
+        result: IncrementalRunResult<Result<()>>,
+        duration: Duration,
+        commands: Vec<CommandReport>,
+    ) -> Self {
+        Self {
+            target_id,
+            result,
+            duration,
+            commands,
+        }
+    }
+}
+
+pub struct CommandReport {
+    pub command: String,
+    pub duration: Duration,
+    pub succeeded: bool,
+}
+
+/// Allows cancelling a running build from another thread, by killing its running command.
+#[derive(Clone, Default)]
+pub struct BuildCancellation {
+    state: Arc<Mutex<CancellationState>>,
+}
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: bool,
+    running_command: Option<CommandKiller>,
+}
+
+impl BuildCancellation {
+    pub fn cancel(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.cancelled = true;
+        if let Some(running_command) = &state.running_command {
+            running_command.kill()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().unwrap().cancelled
+    }
+
+    /// Registers the command currently run by the build, and kills it if the build is already cancelled.
+    fn set_running_command(&self, running_command: Option<&RunningCommand>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.running_command = running_command.map(RunningCommand::get_killer);
+        if state.cancelled {
+            if let Some(running_command) = &state.running_command {
+                running_command.kill()?;
+            }
+        }
+        Ok(())
     }
 }
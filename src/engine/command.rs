@@ -0,0 +1,199 @@
+use super::process_group::{self, ProcessGroup};
+use crate::domain::Target;
+use anyhow::{Context, Result};
+use duct::{cmd, Expression, Handle};
+use std::env;
+use std::io::{BufRead, BufReader, Read};
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+static PREFIX_COLORS: [u8; 6] = [36, 33, 32, 35, 34, 31];
+
+/// Environment variables kept when the environment of a target is cleared.
+pub static PRESERVED_ENV_VARS: [&str; 2] = ["PATH", "HOME"];
+
+#[derive(Clone, Copy)]
+pub struct OutputOptions {
+    /// Whether the output lines of the commands are prefixed with the name of their target.
+    pub prefix: bool,
+    /// Whether the stdout of the commands is redirected to stderr, to keep stdout for the build events.
+    pub stdout_to_stderr: bool,
+}
+
+impl OutputOptions {
+    /// Builds the prefix of the output lines of the target commands.
+    ///
+    /// The prefix is colored when the output is a terminal, and empty when disabled.
+    pub fn get_prefix(&self, target: &Target) -> String {
+        let stream = if self.stdout_to_stderr {
+            atty::Stream::Stderr
+        } else {
+            atty::Stream::Stdout
+        };
+
+        if !self.prefix {
+            String::new()
+        } else if atty::is(stream) {
+            let color = PREFIX_COLORS[target.id % PREFIX_COLORS.len()];
+            format!("\x1b[{}m[{}]\x1b[0m ", color, target.name)
+        } else {
+            format!("[{}] ", target.name)
+        }
+    }
+}
+
+/// Builds the shell expression running a command of the target, in its directory and environment.
+pub fn build_command(target: &Target, command: &str) -> Expression {
+    let expression = cmd!("/bin/sh", "-c", command).dir(&target.path);
+
+    if target.env_clear {
+        let preserved_env = PRESERVED_ENV_VARS
+            .iter()
+            .filter_map(|&name| env::var_os(name).map(|value| (name.into(), value)));
+        let target_env = target
+            .env
+            .iter()
+            .map(|(name, value)| (name.into(), value.into()));
+        let full_env: Vec<(std::ffi::OsString, std::ffi::OsString)> =
+            preserved_env.chain(target_env).collect();
+        expression.full_env(full_env)
+    } else {
+        target
+            .env
+            .iter()
+            .fold(expression, |expression, (name, value)| {
+                expression.env(name, value)
+            })
+    }
+}
+
+/// Starts a command, streaming its stdout and stderr line by line as they are produced.
+pub fn start_command(
+    expression: &Expression,
+    output_options: &OutputOptions,
+    output_prefix: &str,
+) -> Result<RunningCommand> {
+    let (stdout_reader, stdout_writer) =
+        os_pipe::pipe().with_context(|| "Failed to create stdout pipe")?;
+    let (stderr_reader, stderr_writer) =
+        os_pipe::pipe().with_context(|| "Failed to create stderr pipe")?;
+
+    // The writers are dropped along with the expression, so that the readers reach EOF when the command ends.
+    let handle = expression
+        .unchecked()
+        .stdout_file(stdout_writer)
+        .stderr_file(stderr_writer)
+        .start()?;
+
+    Ok(RunningCommand {
+        handle: Arc::new(handle),
+        process_group: None,
+        output_threads: vec![
+            stream_lines(
+                stdout_reader,
+                output_prefix,
+                if output_options.stdout_to_stderr {
+                    OutputStream::Stderr
+                } else {
+                    OutputStream::Stdout
+                },
+            ),
+            stream_lines(stderr_reader, output_prefix, OutputStream::Stderr),
+        ],
+    })
+}
+
+/// Starts a command in its own process group, so that it can be killed along with all the processes it starts.
+pub fn start_command_in_process_group(
+    expression: &Expression,
+    output_options: &OutputOptions,
+    output_prefix: &str,
+) -> Result<RunningCommand> {
+    let mut running_command = start_command(
+        &process_group::configure(expression),
+        output_options,
+        output_prefix,
+    )?;
+    // A single process is started by the expression, which leads the process group.
+    running_command.process_group = running_command
+        .handle
+        .pids()
+        .first()
+        .map(|&pid| Arc::new(ProcessGroup::register(pid)));
+    Ok(running_command)
+}
+
+pub struct RunningCommand {
+    handle: Arc<Handle>,
+    process_group: Option<Arc<ProcessGroup>>,
+    output_threads: Vec<JoinHandle<()>>,
+}
+
+impl RunningCommand {
+    /// Waits for the command to end, and returns its exit status.
+    pub fn wait(self) -> Result<ExitStatus> {
+        let result = self.handle.wait().map(|output| output.status);
+        for output_thread in self.output_threads {
+            output_thread.join().ok();
+        }
+        result.map_err(anyhow::Error::new)
+    }
+
+    pub fn kill(&self) -> Result<()> {
+        self.handle.kill().map_err(anyhow::Error::new)
+    }
+
+    pub fn get_killer(&self) -> CommandKiller {
+        CommandKiller {
+            handle: self.handle.clone(),
+            process_group: self.process_group.clone(),
+        }
+    }
+}
+
+/// Kills a running command from another thread.
+#[derive(Clone)]
+pub struct CommandKiller {
+    handle: Arc<Handle>,
+    process_group: Option<Arc<ProcessGroup>>,
+}
+
+impl CommandKiller {
+    /// Kills the command, along with all the processes it started if it runs in its own process group.
+    pub fn kill(&self) -> Result<()> {
+        if let Some(process_group) = &self.process_group {
+            process_group.kill()?;
+        }
+        self.handle.kill().map_err(anyhow::Error::new)
+    }
+}
+
+enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+fn stream_lines<R>(reader: R, prefix: &str, output_stream: OutputStream) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    let prefix = prefix.to_string();
+    thread::spawn(move || {
+        for line in BufReader::new(reader).split(b'\n') {
+            match line {
+                Ok(line) => {
+                    let line = String::from_utf8_lossy(&line);
+                    match output_stream {
+                        OutputStream::Stdout => println!("{}{}", prefix, line),
+                        OutputStream::Stderr => eprintln!("{}{}", prefix, line),
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to read command output: {}", e);
+                    break;
+                }
+            }
+        }
+    })
+}
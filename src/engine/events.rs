@@ -0,0 +1,147 @@
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Version of the format of the emitted events.
+///
+/// It must be incremented on any backward incompatible change to the events.
+pub const EVENT_FORMAT_VERSION: u32 = 1;
+
+/// Emits build events as JSON lines, meant to be consumed by other programs.
+#[derive(Clone)]
+pub struct EventEmitter {
+    writer: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+impl EventEmitter {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Some(Arc::new(Mutex::new(writer))),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { writer: None }
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(writer) = &self.writer {
+            let message = Message {
+                version: EVENT_FORMAT_VERSION,
+                event,
+            };
+            let mut writer = writer.lock().unwrap();
+            if let Err(e) = serde_json::to_writer(&mut *writer, &message)
+                .map_err(anyhow::Error::new)
+                .and_then(|_| writeln!(writer).map_err(anyhow::Error::new))
+                .and_then(|_| writer.flush().map_err(anyhow::Error::new))
+            {
+                log::error!("Failed to emit event: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Message {
+    version: u32,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TargetScheduled {
+        target: String,
+    },
+    TargetSkipped {
+        target: String,
+        reason: SkipReason,
+    },
+    TargetStarted {
+        target: String,
+    },
+    CommandStarted {
+        target: String,
+        command: String,
+    },
+    CommandFinished {
+        target: String,
+        command: String,
+        exit_code: Option<i32>,
+        duration_ms: u128,
+    },
+    TargetFinished {
+        target: String,
+        duration_ms: u128,
+    },
+    TargetFailed {
+        target: String,
+        error: String,
+        duration_ms: u128,
+    },
+    ServiceStarted {
+        target: String,
+        command: String,
+    },
+    ServiceStopped {
+        target: String,
+    },
+    WatchInvalidated {
+        target: String,
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    NotModified,
+    RestoredFromCache,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventEmitter, SkipReason};
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events_are_emitted_as_versioned_json_lines() {
+        let buffer = SharedBuffer::default();
+        let event_emitter = EventEmitter::new(Box::new(buffer.clone()));
+
+        event_emitter.emit(Event::TargetSkipped {
+            target: "my_target".to_string(),
+            reason: SkipReason::NotModified,
+        });
+        event_emitter.emit(Event::ServiceStopped {
+            target: "my_target".to_string(),
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output,
+            concat!(
+                r#"{"version":1,"event":"target_skipped","target":"my_target","reason":"not_modified"}"#,
+                "\n",
+                r#"{"version":1,"event":"service_stopped","target":"my_target"}"#,
+                "\n",
+            )
+        );
+    }
+}
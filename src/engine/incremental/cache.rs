@@ -0,0 +1,348 @@
+use super::fs_hash::{compute_file_hash, compute_file_hashes_in_paths};
+use super::remote_cache::{RemoteArtifact, RemoteCache};
+use crate::clean::clean_paths;
+use crate::paths::Paths;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the temporary files of objects and manifests being stored concurrently.
+static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Cache of target outputs, made of an optional local cache and an optional remote cache.
+pub struct ArtifactCache {
+    local: Option<LocalCache>,
+    remote: Option<RemoteCache>,
+}
+
+impl ArtifactCache {
+    pub fn new(local: Option<LocalCache>, remote: Option<RemoteCache>) -> Self {
+        Self { local, remote }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.local.is_some() || self.remote.is_some()
+    }
+
+    /// Checks whether the local cache has an entry for this key. The remote cache is not queried.
+    pub fn contains(&self, cache_key: u64) -> bool {
+        match &self.local {
+            Some(local_cache) => local_cache.contains(cache_key),
+            None => false,
+        }
+    }
+
+    /// Restores the outputs cached under the provided key, looking up the local cache first.
+    ///
+    /// Remote cache failures are logged, and considered as cache misses.
+    pub fn restore(&self, cache_key: u64, target_dir: &Path, output_paths: &Paths) -> Result<bool> {
+        if let Some(local_cache) = &self.local {
+            if local_cache.restore(cache_key, target_dir, output_paths)? {
+                return Ok(true);
+            }
+        }
+
+        let remote_cache = match &self.remote {
+            Some(remote_cache) if remote_cache.can_read() => remote_cache,
+            _ => return Ok(false),
+        };
+
+        let artifact = match remote_cache.fetch(cache_key) {
+            Ok(Some(artifact)) => artifact,
+            Ok(None) => return Ok(false),
+            Err(e) => {
+                log::warn!("Remote cache unavailable: {:#}", e);
+                return Ok(false);
+            }
+        };
+
+        // An invalid artifact must not remove the current outputs.
+        artifact.validate(target_dir, output_paths)?;
+        clean_paths(output_paths)?;
+        artifact.write_files(target_dir, output_paths)?;
+
+        if let Some(local_cache) = &self.local {
+            local_cache.store(
+                cache_key,
+                target_dir,
+                &compute_file_hashes_in_paths(output_paths)?,
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    /// Stores the outputs in the cache. Remote cache failures are logged, and otherwise ignored.
+    pub fn store(
+        &self,
+        cache_key: u64,
+        target_dir: &Path,
+        output_file_hashes: &HashMap<PathBuf, u64>,
+    ) -> Result<()> {
+        if let Some(local_cache) = &self.local {
+            local_cache.store(cache_key, target_dir, output_file_hashes)?;
+        }
+
+        if let Some(remote_cache) = &self.remote {
+            if remote_cache.can_write() {
+                if let Err(e) = RemoteArtifact::from_files(
+                    output_file_hashes.keys(),
+                    target_dir,
+                    remote_cache.get_max_artifact_size(),
+                )
+                .and_then(|artifact| remote_cache.push(cache_key, &artifact))
+                {
+                    log::warn!("Failed to push outputs to remote cache: {:#}", e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Content-addressed store of target outputs.
+///
+/// Files are stored once under `objects`, named after their hash.
+/// For each cache key, a manifest under `keys` lists the output files along with their hashes.
+/// As cache keys do not depend on the location of the project, the files are relative to the target directory.
+pub struct LocalCache {
+    cache_dir: PathBuf,
+}
+
+impl LocalCache {
+    pub fn new(checksum_dir: &Path) -> Self {
+        Self {
+            cache_dir: checksum_dir.join("cache"),
+        }
+    }
+
+    pub fn store(
+        &self,
+        cache_key: u64,
+        target_dir: &Path,
+        output_file_hashes: &HashMap<PathBuf, u64>,
+    ) -> Result<()> {
+        let objects_dir = self.get_objects_dir();
+        fs::create_dir_all(&objects_dir)
+            .with_context(|| format!("Failed to create directory {}", objects_dir.display()))?;
+
+        for (file, &hash) in output_file_hashes.iter() {
+            let object_path = self.get_object_path(hash);
+            if !object_path.exists() {
+                self.store_object(file, &object_path)?;
+            }
+        }
+
+        let manifest_path = self.get_manifest_path(cache_key);
+        let keys_dir = manifest_path.parent().unwrap();
+        fs::create_dir_all(keys_dir)
+            .with_context(|| format!("Failed to create directory {}", keys_dir.display()))?;
+        // Outputs located outside of the target directory keep their absolute path.
+        let manifest = CacheManifest {
+            files: output_file_hashes
+                .iter()
+                .map(|(file, &hash)| {
+                    let file = file.strip_prefix(target_dir).unwrap_or(file);
+                    (file.to_path_buf(), hash)
+                })
+                .collect(),
+        };
+        // Like objects, the manifest is moved to its final path once complete.
+        let tmp_manifest_path = get_tmp_path(&manifest_path);
+        let file = File::create(&tmp_manifest_path).with_context(|| {
+            format!(
+                "Failed to create cache manifest {}",
+                tmp_manifest_path.display()
+            )
+        })?;
+        bincode::serialize_into(file, &manifest).with_context(|| {
+            let _ = fs::remove_file(&tmp_manifest_path);
+            format!(
+                "Failed to serialize cache manifest {}",
+                manifest_path.display()
+            )
+        })?;
+        fs::rename(&tmp_manifest_path, &manifest_path).with_context(|| {
+            let _ = fs::remove_file(&tmp_manifest_path);
+            format!("Failed to move cache manifest {}", manifest_path.display())
+        })
+    }
+
+    /// Copies the file to a temporary file of the objects directory, then moves it to its final path.
+    ///
+    /// This way, an interrupted copy never leaves a truncated object behind.
+    fn store_object(&self, file: &Path, object_path: &Path) -> Result<()> {
+        let tmp_object_path = get_tmp_path(object_path);
+        fs::copy(file, &tmp_object_path)
+            .with_context(|| format!("Failed to copy {} to the cache", file.display()))?;
+        fs::rename(&tmp_object_path, object_path).with_context(|| {
+            let _ = fs::remove_file(&tmp_object_path);
+            format!("Failed to move {} to the cache", file.display())
+        })
+    }
+
+    /// Restores the outputs cached under the provided key.
+    ///
+    /// Returns `false` if the cache has no complete entry for this key.
+    /// Objects which do not match their hash are removed from the cache.
+    pub fn restore(&self, cache_key: u64, target_dir: &Path, output_paths: &Paths) -> Result<bool> {
+        let manifest = match self.read_manifest(cache_key)? {
+            Some(manifest) => manifest,
+            None => return Ok(false),
+        };
+
+        for &hash in manifest.files.values() {
+            if !self.is_object_valid(hash) {
+                return Ok(false);
+            }
+        }
+
+        clean_paths(output_paths)?;
+
+        for (file, &hash) in manifest.files.iter() {
+            let file = &target_dir.join(file);
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            fs::copy(self.get_object_path(hash), file)
+                .with_context(|| format!("Failed to restore {} from the cache", file.display()))?;
+        }
+
+        Ok(true)
+    }
+
+    pub fn contains(&self, cache_key: u64) -> bool {
+        self.get_manifest_path(cache_key).exists()
+    }
+
+    fn is_object_valid(&self, hash: u64) -> bool {
+        let object_path = self.get_object_path(hash);
+        if !object_path.exists() {
+            return false;
+        }
+
+        match compute_file_hash(&object_path) {
+            Ok(object_hash) if object_hash == hash => true,
+            _ => {
+                log::warn!("Removing corrupted cache object {}", object_path.display());
+                let _ = fs::remove_file(&object_path);
+                false
+            }
+        }
+    }
+
+    fn read_manifest(&self, cache_key: u64) -> Result<Option<CacheManifest>> {
+        let manifest_path = self.get_manifest_path(cache_key);
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&manifest_path).with_context(|| {
+            format!("Failed to open cache manifest {}", manifest_path.display())
+        })?;
+        let file_size = file
+            .metadata()
+            .with_context(|| format!("Failed to read metadata of {}", manifest_path.display()))?
+            .len();
+        match bincode::config().limit(file_size).deserialize_from(file) {
+            Ok(manifest) => Ok(Some(manifest)),
+            Err(e) => {
+                log::warn!(
+                    "Ignoring corrupted cache manifest {} (Error: {})",
+                    manifest_path.display(),
+                    e
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    fn get_objects_dir(&self) -> PathBuf {
+        self.cache_dir.join("objects")
+    }
+
+    fn get_object_path(&self, hash: u64) -> PathBuf {
+        self.get_objects_dir().join(format!("{:016x}", hash))
+    }
+
+    fn get_manifest_path(&self, cache_key: u64) -> PathBuf {
+        self.cache_dir
+            .join("keys")
+            .join(format!("{:016x}", cache_key))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheManifest {
+    files: HashMap<PathBuf, u64>,
+}
+
+/// Temporary path, in the same directory, where a cache file is written before being moved to `path`.
+fn get_tmp_path(path: &Path) -> PathBuf {
+    path.with_extension(format!(
+        "tmp-{}-{}",
+        process::id(),
+        TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalCache;
+    use crate::engine::incremental::fs_hash::compute_file_hashes_in_paths;
+    use crate::paths::Paths;
+    use std::fs;
+
+    #[test]
+    fn test_restore_should_drop_corrupted_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let output_file = dir.join("dist/index.js");
+        fs::create_dir_all(output_file.parent().unwrap()).unwrap();
+        fs::write(&output_file, "content").unwrap();
+        let output_paths = Paths::new(dir, &["dist".to_string()]).unwrap();
+        let output_file_hashes = compute_file_hashes_in_paths(&output_paths).unwrap();
+
+        let cache = LocalCache::new(&dir.join(".zinoma"));
+        cache.store(1, dir, &output_file_hashes).unwrap();
+        assert!(cache.restore(1, dir, &output_paths).unwrap());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "content");
+
+        let object_path = cache.get_object_path(*output_file_hashes.values().next().unwrap());
+        fs::write(&object_path, "cont").unwrap();
+        assert!(!cache.restore(1, dir, &output_paths).unwrap());
+        assert!(!object_path.exists());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "content");
+    }
+
+    #[test]
+    fn test_restore_should_write_the_outputs_in_the_target_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let (target_dir, moved_target_dir) = (dir.join("project"), dir.join("moved_project"));
+        let output_file = target_dir.join("dist/index.js");
+        fs::create_dir_all(output_file.parent().unwrap()).unwrap();
+        fs::write(&output_file, "content").unwrap();
+        let output_paths = Paths::new(&target_dir, &["dist".to_string()]).unwrap();
+        let output_file_hashes = compute_file_hashes_in_paths(&output_paths).unwrap();
+
+        let cache = LocalCache::new(&dir.join(".zinoma"));
+        cache.store(1, &target_dir, &output_file_hashes).unwrap();
+        fs::remove_file(&output_file).unwrap();
+        let moved_output_paths = Paths::new(&moved_target_dir, &["dist".to_string()]).unwrap();
+        assert!(cache
+            .restore(1, &moved_target_dir, &moved_output_paths)
+            .unwrap());
+        assert_eq!(
+            fs::read_to_string(moved_target_dir.join("dist/index.js")).unwrap(),
+            "content"
+        );
+        assert!(!output_file.exists());
+    }
+}
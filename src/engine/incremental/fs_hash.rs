@@ -1,15 +1,17 @@
+use crate::paths::Paths;
 use anyhow::{Context, Result};
 use rayon::prelude::*;
 use seahash::SeaHasher;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fs;
 use std::hash::Hasher;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
 
-pub fn compute_file_hashes_in_paths(paths: &[PathBuf]) -> Result<HashMap<PathBuf, u64>> {
-    let files = list_files(paths).with_context(|| "Failed to list checksum files".to_string())?;
+pub fn compute_file_hashes_in_paths(paths: &Paths) -> Result<HashMap<PathBuf, u64>> {
+    let files = paths
+        .list_files()
+        .with_context(|| "Failed to list checksum files".to_string())?;
 
     files
         .into_par_iter()
@@ -22,11 +24,12 @@ pub fn compute_file_hashes_in_paths(paths: &[PathBuf]) -> Result<HashMap<PathBuf
 }
 
 pub fn file_hashes_eq(
-    lookup_paths: &[PathBuf],
+    lookup_paths: &Paths,
     saved_file_hashes: &HashMap<PathBuf, u64>,
 ) -> Result<bool> {
-    let files =
-        list_files(lookup_paths).with_context(|| "Failed to list checksum files".to_string())?;
+    let files = lookup_paths
+        .list_files()
+        .with_context(|| "Failed to list checksum files".to_string())?;
 
     if files.len() != saved_file_hashes.len() {
         return Ok(false);
@@ -45,25 +48,48 @@ pub fn file_hashes_eq(
     }))
 }
 
-fn list_files(paths: &[PathBuf]) -> Result<HashSet<PathBuf>> {
-    let mut files = HashSet::new();
+/// Lists the differences between the files currently found in `lookup_paths` and the saved file hashes.
+pub fn diff_file_hashes(
+    lookup_paths: &Paths,
+    saved_file_hashes: &HashMap<PathBuf, u64>,
+) -> Result<FileHashesDiff> {
+    let file_hashes = compute_file_hashes_in_paths(lookup_paths)?;
 
-    for path in paths {
-        for entry in WalkDir::new(path) {
-            let path = entry
-                .with_context(|| format!("Failed to traverse directory {}", path.display()))?
-                .path()
-                .to_path_buf();
-            if path.is_file() {
-                files.insert(path);
-            }
+    let mut diff = FileHashesDiff::default();
+    for (file, hash) in file_hashes.iter() {
+        match saved_file_hashes.get(file) {
+            None => diff.added.push(file.to_path_buf()),
+            Some(saved_hash) if saved_hash != hash => diff.modified.push(file.to_path_buf()),
+            _ => {}
+        }
+    }
+    for file in saved_file_hashes.keys() {
+        if !file_hashes.contains_key(file) {
+            diff.removed.push(file.to_path_buf());
         }
     }
 
-    Ok(files)
+    diff.added.sort();
+    diff.removed.sort();
+    diff.modified.sort();
+
+    Ok(diff)
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct FileHashesDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+}
+
+impl FileHashesDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
 }
 
-fn compute_file_hash(file_path: &Path) -> Result<u64> {
+pub fn compute_file_hash(file_path: &Path) -> Result<u64> {
     let mut hasher = SeaHasher::default();
     let file = fs::File::open(file_path)
         .with_context(|| format!("Failed to open file {}", file_path.display()))?;
@@ -1,104 +1,286 @@
+mod cache;
 mod fs_hash;
+mod remote_cache;
 
+use super::command::{build_command, PRESERVED_ENV_VARS};
 use crate::domain::Target;
-use crate::engine::incremental::fs_hash::file_hashes_eq;
 use anyhow::{Context, Error, Result};
-use fs_hash::compute_file_hashes_in_paths;
+pub use cache::{ArtifactCache, LocalCache};
+use fs_hash::{compute_file_hashes_in_paths, diff_file_hashes, file_hashes_eq, FileHashesDiff};
+pub use remote_cache::{RemoteCache, RemoteCacheMode, DEFAULT_MAX_ARTIFACT_SIZE};
+use seahash::SeaHasher;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
 #[derive(PartialEq)]
 pub enum IncrementalRunResult<T> {
     Skipped,
+    Restored,
     Run(T),
 }
 
 pub struct IncrementalRunner<'a> {
     checksum_dir: &'a Path,
+    explain: bool,
+    cache: ArtifactCache,
 }
 
 impl<'a> IncrementalRunner<'a> {
-    pub fn new(checksum_dir: &'a Path) -> Self {
-        Self { checksum_dir }
+    /// When `explain` is set, the reason of every non-skipped build is logged.
+    ///
+    /// Target outputs are stored in the `cache` after each successful build,
+    /// and restored from it instead of running the build when the same inputs are found again.
+    pub fn new(checksum_dir: &'a Path, explain: bool, cache: ArtifactCache) -> Self {
+        Self {
+            checksum_dir,
+            explain,
+            cache,
+        }
     }
 
-    pub fn run<T, F>(&self, target: &Target, function: F) -> Result<IncrementalRunResult<Result<T>>>
+    /// Runs the function if the inputs of the target changed since its last successful execution.
+    ///
+    /// The checksums are not saved if the build gets cancelled (according to `is_cancelled`) before they are written:
+    /// they could otherwise describe inputs which the build did not take into account.
+    pub fn run<T, F, C>(
+        &self,
+        target: &Target,
+        is_cancelled: C,
+        function: F,
+    ) -> Result<IncrementalRunResult<Result<T>>>
     where
-        F: Fn() -> Result<T>,
+        F: FnOnce() -> Result<T>,
+        C: Fn() -> bool,
     {
-        if self.files_have_not_changed_since_last_successful_execution(target)? {
+        // The inputs are hashed once, before the build: the checksums and the cache key then describe
+        // the inputs which the build took into account, even if they get modified meanwhile.
+        let input_values = compute_input_value_hashes(target)?;
+        let input_file_hashes = compute_file_hashes_in_paths(&target.input_paths)?;
+
+        if self.inputs_have_not_changed_since_last_successful_execution(
+            target,
+            &input_file_hashes,
+            &input_values,
+        )? {
             return Ok(IncrementalRunResult::Skipped);
         }
 
-        self.remove_target_checksums(&target)?;
+        if self.explain {
+            if let Some(build_reason) = self.explain(target, &input_values)? {
+                log::info!("{} - Build required: {}", target.name, build_reason);
+            }
+        }
+
+        self.remove_target_checksums(target)?;
+
+        let cache_key = if self.cache.is_enabled() && is_cacheable(target) {
+            Some(compute_cache_key(target, &input_file_hashes, &input_values))
+        } else {
+            None
+        };
+
+        if let Some(cache_key) = cache_key {
+            if self.restore_from_cache(target, cache_key) {
+                if let Some(target_checksums) =
+                    compute_target_checksums(target, &input_file_hashes, &input_values)?
+                {
+                    check_not_cancelled(&is_cancelled)?;
+                    self.write_target_checksums(target, &target_checksums)?;
+                }
+                return Ok(IncrementalRunResult::Restored);
+            }
+        }
 
         let result = function();
 
         if result.is_ok() {
-            if let Some(target_checksums) = compute_target_checksums(target)? {
-                self.write_target_checksums(&target, &target_checksums)?;
+            if let Some(target_checksums) =
+                compute_target_checksums(target, &input_file_hashes, &input_values)?
+            {
+                check_not_cancelled(&is_cancelled)?;
+                self.write_target_checksums(target, &target_checksums)?;
+                if let Some(cache_key) = cache_key {
+                    self.store_in_cache(target, cache_key, &target_checksums.outputs);
+                }
             }
         }
 
         Ok(IncrementalRunResult::Run(result))
     }
 
+    /// Checks whether the outputs of the target could be restored from the local cache.
+    pub fn is_cached(&self, target: &Target, input_values: &InputValueHashes) -> Result<bool> {
+        if !self.cache.is_enabled() || !is_cacheable(target) {
+            return Ok(false);
+        }
+
+        let input_file_hashes = compute_file_hashes_in_paths(&target.input_paths)?;
+        Ok(self
+            .cache
+            .contains(compute_cache_key(target, &input_file_hashes, input_values)))
+    }
+
+    /// Hashes the inputs of the target which are not files, without running anything.
+    ///
+    /// The `input_commands` are not run: their outputs are assumed unchanged since the last successful build.
+    pub fn compute_dry_run_input_values(&self, target: &Target) -> Result<InputValueHashes> {
+        let commands = match self
+            .read_target_checksums(target)
+            .with_context(|| format!("Failed to read saved checksums for {}", target.name))?
+        {
+            SavedChecksums::Valid(saved_checksums) => saved_checksums.values.commands,
+            _ => BTreeMap::new(),
+        };
+        Ok(InputValueHashes {
+            env: compute_env_hashes(target),
+            commands,
+        })
+    }
+
+    /// Failing to restore the outputs is not an error: the target then simply gets built.
+    fn restore_from_cache(&self, target: &Target, cache_key: u64) -> bool {
+        match self
+            .cache
+            .restore(cache_key, &target.path, &target.output_paths)
+        {
+            Ok(restored) => {
+                if restored {
+                    log::info!("{} - Outputs restored from cache", target.name);
+                }
+                restored
+            }
+            Err(e) => {
+                log::warn!(
+                    "{} - Failed to restore outputs from cache: {:#}",
+                    target.name,
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    fn store_in_cache(
+        &self,
+        target: &Target,
+        cache_key: u64,
+        output_file_hashes: &HashMap<PathBuf, u64>,
+    ) {
+        if let Err(e) = self
+            .cache
+            .store(cache_key, &target.path, output_file_hashes)
+        {
+            log::warn!(
+                "{} - Failed to store outputs in cache: {:#}",
+                target.name,
+                e
+            );
+        }
+    }
+
+    /// Explains why the target needs to be built, or returns `None` if it is up to date.
+    pub fn explain(
+        &self,
+        target: &Target,
+        input_values: &InputValueHashes,
+    ) -> Result<Option<BuildReason>> {
+        if !has_inputs(target) {
+            return Ok(Some(BuildReason::NoInputs));
+        }
+
+        let saved_checksums = match self
+            .read_target_checksums(target)
+            .with_context(|| format!("Failed to read saved checksums for {}", target.name))?
+        {
+            SavedChecksums::Missing => return Ok(Some(BuildReason::MissingChecksums)),
+            SavedChecksums::Corrupted(e) => return Ok(Some(BuildReason::CorruptedChecksums(e))),
+            SavedChecksums::Valid(saved_checksums) => saved_checksums,
+        };
+
+        if saved_checksums.definition != compute_target_definition_fingerprint(target) {
+            return Ok(Some(BuildReason::DefinitionChanged));
+        }
+
+        let variables = diff_value_hashes(&input_values.env, &saved_checksums.values.env);
+        if !variables.is_empty() {
+            return Ok(Some(BuildReason::EnvChanged(variables)));
+        }
+
+        let commands = diff_value_hashes(&input_values.commands, &saved_checksums.values.commands);
+        if !commands.is_empty() {
+            return Ok(Some(BuildReason::CommandOutputsChanged(commands)));
+        }
+
+        let inputs = diff_file_hashes(&target.input_paths, &saved_checksums.inputs)?;
+        let outputs = diff_file_hashes(&target.output_paths, &saved_checksums.outputs)?;
+        if inputs.is_empty() && outputs.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(BuildReason::FilesChanged { inputs, outputs }))
+        }
+    }
+
     fn get_checksum_file_path(&self, target: &Target) -> PathBuf {
         self.checksum_dir.join(format!("{}.checksum", target.name))
     }
 
-    fn files_have_not_changed_since_last_successful_execution(
+    fn inputs_have_not_changed_since_last_successful_execution(
         &self,
         target: &Target,
+        input_file_hashes: &HashMap<PathBuf, u64>,
+        input_values: &InputValueHashes,
     ) -> Result<bool> {
         let saved_checksums = self
             .read_target_checksums(target)
             .with_context(|| format!("Failed to read saved checksums for {}", target.name))?;
 
         match saved_checksums {
-            Some(saved_checksums) => saved_checksums.eq_fs_checksum(target).with_context(|| {
-                format!(
-                    "Failed to compare saved checksums with filesystem checksums for {}",
-                    target.name
-                )
-            }),
+            SavedChecksums::Valid(saved_checksums) => saved_checksums
+                .eq_current_checksums(target, input_file_hashes, input_values)
+                .with_context(|| {
+                    format!(
+                        "Failed to compare saved checksums with filesystem checksums for {}",
+                        target.name
+                    )
+                }),
             _ => Ok(false),
         }
     }
 
-    fn read_target_checksums(&self, target: &Target) -> Result<Option<TargetChecksums>> {
-        // Might want to check for some errors like permission denied.
-        fs::create_dir(&self.checksum_dir).ok();
-
+    /// Reads the saved checksums of the target, without altering the checksum directory.
+    fn read_target_checksums(&self, target: &Target) -> Result<SavedChecksums> {
         let file_path = self.get_checksum_file_path(target);
         if file_path.exists() {
             let file = File::open(&file_path)
                 .with_context(|| format!("Failed to open checksum file {}", file_path.display()))?;
-            match bincode::deserialize_from(file) {
-                Ok(checksums) => Ok(Some(checksums)),
+            let file_size = file
+                .metadata()
+                .with_context(|| format!("Failed to read metadata of {}", file_path.display()))?
+                .len();
+            // Limiting the deserialization to the file size prevents huge allocations on corrupted files.
+            match bincode::config().limit(file_size).deserialize_from(file) {
+                Ok(checksums) => Ok(SavedChecksums::Valid(checksums)),
                 Err(e) => {
-                    log::trace!(
-                        "{} - Dropping corrupted checksum file (Error: {})",
-                        &target.name,
-                        e
-                    );
-                    self.remove_target_checksums(&target)?;
-                    Ok(None)
+                    log::trace!("{} - Corrupted checksum file (Error: {})", &target.name, e);
+                    Ok(SavedChecksums::Corrupted(e.to_string()))
                 }
             }
         } else {
-            Ok(None)
+            Ok(SavedChecksums::Missing)
         }
     }
 
     fn remove_target_checksums(&self, target: &Target) -> Result<()> {
         let checksum_file = &self.get_checksum_file_path(target);
         if checksum_file.exists() {
-            fs::remove_file(&checksum_file).with_context(|| {
+            fs::remove_file(checksum_file).with_context(|| {
                 format!("Failed to delete checksum file {}", checksum_file.display())
             })?;
         }
@@ -106,6 +288,12 @@ impl<'a> IncrementalRunner<'a> {
     }
 
     fn write_target_checksums(&self, target: &Target, checksums: &TargetChecksums) -> Result<()> {
+        fs::create_dir_all(self.checksum_dir).with_context(|| {
+            format!(
+                "Failed to create checksum directory {}",
+                self.checksum_dir.display()
+            )
+        })?;
         let file_path = self.get_checksum_file_path(target);
         let file = File::create(&file_path)
             .with_context(|| format!("Failed to create checksum file {}", file_path.display()))?;
@@ -140,26 +328,426 @@ impl<'a> IncrementalRunner<'a> {
     }
 }
 
-fn compute_target_checksums(target: &Target) -> Result<Option<TargetChecksums>> {
-    if target.input_paths.is_empty() {
+fn compute_target_checksums(
+    target: &Target,
+    input_file_hashes: &HashMap<PathBuf, u64>,
+    input_values: &InputValueHashes,
+) -> Result<Option<TargetChecksums>> {
+    if !has_inputs(target) {
         Ok(None)
     } else {
         Ok(Some(TargetChecksums {
-            inputs: compute_file_hashes_in_paths(&target.input_paths)?,
+            definition: compute_target_definition_fingerprint(target),
+            inputs: input_file_hashes.clone(),
             outputs: compute_file_hashes_in_paths(&target.output_paths)?,
+            values: input_values.clone(),
         }))
     }
 }
 
+/// Targets without inputs are always built.
+fn has_inputs(target: &Target) -> bool {
+    !target.input_paths.is_empty()
+        || !target.input_env.is_empty()
+        || !target.input_commands.is_empty()
+}
+
+fn is_cacheable(target: &Target) -> bool {
+    has_inputs(target) && !target.output_paths.is_empty()
+}
+
+fn compute_input_value_hashes(target: &Target) -> Result<InputValueHashes> {
+    Ok(InputValueHashes {
+        env: compute_env_hashes(target),
+        commands: compute_command_hashes(target)?,
+    })
+}
+
+/// Hashes the values of the `input_env` variables, as seen by the target commands.
+///
+/// When the environment of the target is cleared, only the preserved variables are read from the process environment.
+/// Only hashes are saved, as these variables may hold secrets.
+fn compute_env_hashes(target: &Target) -> BTreeMap<String, u64> {
+    target
+        .input_env
+        .iter()
+        .map(|variable| {
+            let value = match target.env.get(variable) {
+                Some(value) => Some(value.to_string()),
+                None if target.env_clear && !PRESERVED_ENV_VARS.contains(&variable.as_str()) => {
+                    None
+                }
+                None => env::var(variable).ok(),
+            };
+            let mut hasher = SeaHasher::default();
+            value.hash(&mut hasher);
+            (variable.to_string(), hasher.finish())
+        })
+        .collect()
+}
+
+/// Runs the `input_commands` of the target, and hashes their stdout.
+fn compute_command_hashes(target: &Target) -> Result<BTreeMap<String, u64>> {
+    target
+        .input_commands
+        .iter()
+        .map(|command| {
+            let output = build_command(target, command)
+                .stdout_capture()
+                .stderr_capture()
+                .unchecked()
+                .run()
+                .with_context(|| format!("Failed to run input command \"{}\"", command))?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "Input command \"{}\" failed ({}): {}",
+                    command,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+
+            let mut hasher = SeaHasher::default();
+            Hasher::write(&mut hasher, &output.stdout);
+            Ok((command.to_string(), hasher.finish()))
+        })
+        .collect()
+}
+
+/// Lists the keys whose hashes differ between the current and the saved values.
+fn diff_value_hashes(
+    value_hashes: &BTreeMap<String, u64>,
+    saved_value_hashes: &BTreeMap<String, u64>,
+) -> Vec<String> {
+    let keys: BTreeSet<_> = value_hashes
+        .keys()
+        .chain(saved_value_hashes.keys())
+        .filter(|&key| value_hashes.get(key) != saved_value_hashes.get(key))
+        .cloned()
+        .collect();
+    keys.into_iter().collect()
+}
+
+/// Identifies the outputs of a target, based on its definition and on its inputs.
+///
+/// Like the definition fingerprint, it does not depend on the location of the project:
+/// the key of a build can be found on other machines.
+fn compute_cache_key(
+    target: &Target,
+    input_file_hashes: &HashMap<PathBuf, u64>,
+    input_values: &InputValueHashes,
+) -> u64 {
+    let mut input_file_hashes: Vec<_> = input_file_hashes
+        .iter()
+        .map(|(file, hash)| (get_project_relative_path(target, file), hash))
+        .collect();
+    input_file_hashes.sort();
+
+    let mut hasher = SeaHasher::default();
+    compute_target_definition_fingerprint(target).hash(&mut hasher);
+    input_file_hashes.hash(&mut hasher);
+    input_values.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn check_not_cancelled<C: Fn() -> bool>(is_cancelled: &C) -> Result<()> {
+    if is_cancelled() {
+        return Err(anyhow::anyhow!("Build cancelled"));
+    }
+    Ok(())
+}
+
+/// Fingerprints the parts of the target definition which affect its build.
+pub fn compute_target_definition_fingerprint(target: &Target) -> u64 {
+    let mut hasher = SeaHasher::default();
+    get_project_relative_path(target, &target.path).hash(&mut hasher);
+    target.build_list.hash(&mut hasher);
+    target.service.hash(&mut hasher);
+    target
+        .input_paths
+        .hash_relative_to(&target.project_dir, &mut hasher);
+    target
+        .output_paths
+        .hash_relative_to(&target.project_dir, &mut hasher);
+    target.env.hash(&mut hasher);
+    target.env_clear.hash(&mut hasher);
+    target.input_env.hash(&mut hasher);
+    target.input_commands.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Paths are hashed relative to the project directory, so that the hashes do not depend on its location.
+fn get_project_relative_path<'p>(target: &Target, path: &'p Path) -> &'p Path {
+    path.strip_prefix(&target.project_dir).unwrap_or(path)
+}
+
+enum SavedChecksums {
+    Missing,
+    Corrupted(String),
+    Valid(TargetChecksums),
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 struct TargetChecksums {
+    definition: u64,
     inputs: HashMap<PathBuf, u64>,
     outputs: HashMap<PathBuf, u64>,
+    values: InputValueHashes,
+}
+
+/// Hashes of the target inputs which are not files.
+#[derive(Clone, Hash, Serialize, Deserialize, PartialEq)]
+pub struct InputValueHashes {
+    /// Hashes of the `input_env` variables, by name.
+    env: BTreeMap<String, u64>,
+    /// Hashes of the stdout of the `input_commands`, by command.
+    commands: BTreeMap<String, u64>,
 }
 
 impl TargetChecksums {
-    fn eq_fs_checksum(&self, target: &Target) -> Result<bool> {
-        Ok(file_hashes_eq(&target.input_paths, &self.inputs)?
-            && file_hashes_eq(&target.output_paths, &self.outputs)?)
+    fn eq_current_checksums(
+        &self,
+        target: &Target,
+        input_file_hashes: &HashMap<PathBuf, u64>,
+        input_values: &InputValueHashes,
+    ) -> Result<bool> {
+        Ok(
+            self.definition == compute_target_definition_fingerprint(target)
+                && &self.values == input_values
+                && &self.inputs == input_file_hashes
+                && file_hashes_eq(&target.output_paths, &self.outputs)?,
+        )
+    }
+}
+
+pub enum BuildReason {
+    NoInputs,
+    MissingChecksums,
+    CorruptedChecksums(String),
+    DefinitionChanged,
+    EnvChanged(Vec<String>),
+    CommandOutputsChanged(Vec<String>),
+    FilesChanged {
+        inputs: FileHashesDiff,
+        outputs: FileHashesDiff,
+    },
+}
+
+impl fmt::Display for BuildReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildReason::NoInputs => write!(
+                f,
+                "no input_paths, input_env nor input_commands (the target is always built)"
+            ),
+            BuildReason::MissingChecksums => write!(
+                f,
+                "no checksum file (the target never completed successfully)"
+            ),
+            BuildReason::CorruptedChecksums(e) => write!(f, "corrupted checksum file ({})", e),
+            BuildReason::DefinitionChanged => write!(f, "the target definition changed"),
+            BuildReason::EnvChanged(variables) => write!(
+                f,
+                "environment variables modified: {}",
+                variables.join(", ")
+            ),
+            BuildReason::CommandOutputsChanged(commands) => {
+                write!(f, "input command outputs modified: {}", commands.join(", "))
+            }
+            BuildReason::FilesChanged { inputs, outputs } => {
+                let descriptions: Vec<_> = [("input", inputs), ("output", outputs)]
+                    .iter()
+                    .flat_map(|(kind, diff)| describe_file_hashes_diff(kind, diff))
+                    .collect();
+                write!(f, "{}", descriptions.join("; "))
+            }
+        }
+    }
+}
+
+fn describe_file_hashes_diff(kind: &str, diff: &FileHashesDiff) -> Vec<String> {
+    [
+        ("added", &diff.added),
+        ("removed", &diff.removed),
+        ("modified", &diff.modified),
+    ]
+    .iter()
+    .filter(|(_change, files)| !files.is_empty())
+    .map(|(change, files)| {
+        let files: Vec<_> = files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        format!("{} files {}: {}", kind, change, files.join(", "))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        compute_cache_key, compute_env_hashes, compute_target_definition_fingerprint, BuildReason,
+        InputValueHashes,
+    };
+    use crate::domain::tests::build_target;
+    use crate::engine::incremental::fs_hash::FileHashesDiff;
+    use crate::paths::{IgnoreFiles, Paths};
+    use std::collections::{BTreeMap, HashMap};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_definition_fingerprint_changes_with_target_definition() {
+        let target = build_target(0, vec![]);
+        let fingerprint = compute_target_definition_fingerprint(&target);
+
+        let mut modified_target = target.clone();
+        modified_target.build_list = vec!["cargo build".to_string()];
+        assert_ne!(
+            compute_target_definition_fingerprint(&modified_target),
+            fingerprint
+        );
+
+        let mut modified_target = target.clone();
+        modified_target.service = Some("cargo run".to_string());
+        assert_ne!(
+            compute_target_definition_fingerprint(&modified_target),
+            fingerprint
+        );
+
+        let mut modified_target = target.clone();
+        modified_target.input_paths = Paths::new(Path::new("."), &["src".to_string()]).unwrap();
+        assert_ne!(
+            compute_target_definition_fingerprint(&modified_target),
+            fingerprint
+        );
+
+        let input_paths = Paths::new(Path::new("."), &["src".to_string()]).unwrap();
+        let mut modified_target = target.clone();
+        modified_target.input_paths = input_paths.clone();
+        let mut ignoring_target = target.clone();
+        ignoring_target.input_paths =
+            input_paths.with_ignore_files(Arc::new(IgnoreFiles::new(Path::new(".")).unwrap()));
+        assert_ne!(
+            compute_target_definition_fingerprint(&ignoring_target),
+            compute_target_definition_fingerprint(&modified_target)
+        );
+
+        let mut modified_target = target.clone();
+        modified_target
+            .env
+            .insert("NODE_ENV".to_string(), "production".to_string());
+        assert_ne!(
+            compute_target_definition_fingerprint(&modified_target),
+            fingerprint
+        );
+
+        let mut modified_target = target;
+        modified_target.output_paths = Paths::new(Path::new("."), &["target".to_string()]).unwrap();
+        assert_ne!(
+            compute_target_definition_fingerprint(&modified_target),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn test_definition_fingerprint_does_not_depend_on_the_project_location() {
+        let build_located_target = |project_dir: PathBuf| {
+            let mut target = build_target(0, vec![]);
+            target.path = project_dir.join("frontend");
+            target.input_paths = Paths::new(&target.path, &["src".to_string()]).unwrap();
+            target.output_paths = Paths::new(&target.path, &["dist".to_string()]).unwrap();
+            target.project_dir = project_dir;
+            target
+        };
+
+        let target = build_located_target(std::env::temp_dir().join("project"));
+        let moved_target = build_located_target(std::env::temp_dir().join("moved_project"));
+        assert_eq!(
+            compute_target_definition_fingerprint(&moved_target),
+            compute_target_definition_fingerprint(&target)
+        );
+    }
+
+    #[test]
+    fn test_cache_key_does_not_depend_on_the_project_location() {
+        let build_located_target = |project_dir: PathBuf| {
+            let mut target = build_target(0, vec![]);
+            target.path = project_dir.join("frontend");
+            target.input_paths = Paths::new(&target.path, &["src".to_string()]).unwrap();
+            target.output_paths = Paths::new(&target.path, &["dist".to_string()]).unwrap();
+            target.project_dir = project_dir;
+            let input_file_hashes: HashMap<_, _> =
+                vec![(target.path.join("src").join("index.ts"), 1)]
+                    .into_iter()
+                    .collect();
+            (target, input_file_hashes)
+        };
+        let input_values = InputValueHashes {
+            env: BTreeMap::new(),
+            commands: BTreeMap::new(),
+        };
+
+        let (target, input_file_hashes) =
+            build_located_target(std::env::temp_dir().join("project"));
+        let (moved_target, moved_input_file_hashes) =
+            build_located_target(std::env::temp_dir().join("moved_project"));
+        assert_eq!(
+            compute_cache_key(&moved_target, &moved_input_file_hashes, &input_values),
+            compute_cache_key(&target, &input_file_hashes, &input_values)
+        );
+    }
+
+    #[test]
+    fn test_env_hashes_use_the_target_env() {
+        let mut target = build_target(0, vec![]);
+        target.input_env = vec!["ZINOMA_TEST_INPUT_ENV".to_string()];
+        let unset_hashes = compute_env_hashes(&target);
+
+        target
+            .env
+            .insert("ZINOMA_TEST_INPUT_ENV".to_string(), "value".to_string());
+        let env_hashes = compute_env_hashes(&target);
+
+        assert_eq!(env_hashes.len(), 1);
+        assert_ne!(env_hashes, unset_hashes);
+    }
+
+    #[test]
+    fn test_env_hashes_ignore_the_process_env_when_cleared() {
+        let mut target = build_target(0, vec![]);
+        target.input_env = vec!["CARGO_PKG_NAME".to_string(), "PATH".to_string()];
+        target.env_clear = true;
+        let cleared_hashes = compute_env_hashes(&target);
+
+        target.env_clear = false;
+        let env_hashes = compute_env_hashes(&target);
+
+        assert_ne!(
+            cleared_hashes["CARGO_PKG_NAME"],
+            env_hashes["CARGO_PKG_NAME"]
+        );
+        assert_eq!(cleared_hashes["PATH"], env_hashes["PATH"]);
+    }
+
+    #[test]
+    fn test_build_reason_lists_changed_files() {
+        let build_reason = BuildReason::FilesChanged {
+            inputs: FileHashesDiff {
+                added: vec![PathBuf::from("src/new.rs")],
+                removed: vec![],
+                modified: vec![PathBuf::from("src/a.rs"), PathBuf::from("src/b.rs")],
+            },
+            outputs: FileHashesDiff {
+                added: vec![],
+                removed: vec![PathBuf::from("target/bin")],
+                modified: vec![],
+            },
+        };
+
+        assert_eq!(
+            build_reason.to_string(),
+            "input files added: src/new.rs; input files modified: src/a.rs, src/b.rs; output files removed: target/bin"
+        );
     }
 }
@@ -0,0 +1,325 @@
+use crate::paths::Paths;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Maximum size of an artifact, in bytes, unless configured otherwise.
+pub const DEFAULT_MAX_ARTIFACT_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Target outputs cache, stored on a remote HTTP server.
+///
+/// The outputs of a target are bundled in a single artifact,
+/// fetched with `GET <url>/<cache_key>` and pushed with `PUT <url>/<cache_key>`.
+pub struct RemoteCache {
+    url: String,
+    mode: RemoteCacheMode,
+    /// Artifacts larger than this size (in bytes) are neither fetched nor pushed.
+    max_artifact_size: u64,
+    agent: ureq::Agent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RemoteCacheMode {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl RemoteCacheMode {
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode {
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "read_write" => Ok(Self::ReadWrite),
+            _ => Err(anyhow::anyhow!(
+                "Invalid remote cache mode {} (expected read, write or read_write)",
+                mode
+            )),
+        }
+    }
+}
+
+impl RemoteCache {
+    pub fn new(url: &str, mode: RemoteCacheMode, max_artifact_size: u64) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            mode,
+            max_artifact_size,
+            // Large artifacts may take a long time to transfer: the transfers are only interrupted when the server stalls.
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .timeout_read(Duration::from_secs(60))
+                .timeout_write(Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    pub fn can_read(&self) -> bool {
+        self.mode != RemoteCacheMode::Write
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.mode != RemoteCacheMode::Read
+    }
+
+    /// Downloads the artifact stored under the provided key, if any.
+    pub fn fetch(&self, cache_key: u64) -> Result<Option<RemoteArtifact>> {
+        let url = self.get_artifact_url(cache_key);
+        let response = match self.agent.get(&url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("GET {} failed", url))),
+        };
+
+        let content_length = response
+            .header("Content-Length")
+            .and_then(|content_length| content_length.parse::<u64>().ok());
+        if let Some(content_length) = content_length {
+            self.check_artifact_size(content_length)
+                .with_context(|| format!("Rejected artifact from GET {}", url))?;
+        }
+
+        // The body is read up to one byte past the limit, to detect larger bodies without a Content-Length.
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(self.max_artifact_size + 1)
+            .read_to_end(&mut body)
+            .with_context(|| format!("Failed to read response of GET {}", url))?;
+        self.check_artifact_size(body.len() as u64)
+            .with_context(|| format!("Rejected artifact from GET {}", url))?;
+        let artifact = bincode::config()
+            .limit(self.max_artifact_size)
+            .deserialize(&body)
+            .with_context(|| format!("Invalid artifact received from {}", url))?;
+
+        Ok(Some(artifact))
+    }
+
+    pub fn push(&self, cache_key: u64, artifact: &RemoteArtifact) -> Result<()> {
+        let url = self.get_artifact_url(cache_key);
+        let body = bincode::serialize(artifact).with_context(|| "Failed to serialize artifact")?;
+        self.check_artifact_size(body.len() as u64)?;
+        self.agent
+            .put(&url)
+            .send_bytes(&body)
+            .with_context(|| format!("PUT {} failed", url))?;
+
+        Ok(())
+    }
+
+    pub fn get_max_artifact_size(&self) -> u64 {
+        self.max_artifact_size
+    }
+
+    fn check_artifact_size(&self, size: u64) -> Result<()> {
+        if size > self.max_artifact_size {
+            return Err(anyhow::anyhow!(
+                "Artifact of {} bytes exceeds the maximum size of {} bytes",
+                size,
+                self.max_artifact_size
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_artifact_url(&self, cache_key: u64) -> String {
+        format!("{}/{:016x}", self.url, cache_key)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RemoteArtifact {
+    files: Vec<RemoteArtifactFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteArtifactFile {
+    /// Path of the file, relative to the directory of the target.
+    path: PathBuf,
+    content: Vec<u8>,
+    readonly: bool,
+    mode: Option<u32>,
+}
+
+impl RemoteArtifact {
+    /// Bundles the output files of a target located in `target_dir`.
+    ///
+    /// Fails if the files are larger than `max_size` bytes in total.
+    pub fn from_files<'a, I>(files: I, target_dir: &Path, max_size: u64) -> Result<Self>
+    where
+        I: Iterator<Item = &'a PathBuf>,
+    {
+        let mut total_size = 0;
+        let files = files
+            .map(|path| {
+                let metadata = fs::metadata(path)
+                    .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+                total_size += metadata.len();
+                if total_size > max_size {
+                    return Err(anyhow::anyhow!(
+                        "Outputs exceed the maximum artifact size of {} bytes",
+                        max_size
+                    ));
+                }
+
+                let relative_path = path
+                    .strip_prefix(target_dir)
+                    .ok()
+                    .filter(|relative_path| is_safe_relative_path(relative_path))
+                    .with_context(|| {
+                        format!(
+                            "{} is outside of the target directory {}",
+                            path.display(),
+                            target_dir.display()
+                        )
+                    })?;
+                let content =
+                    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+                let permissions = metadata.permissions();
+                Ok(RemoteArtifactFile {
+                    path: relative_path.to_path_buf(),
+                    content,
+                    readonly: permissions.readonly(),
+                    mode: get_mode(&permissions),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { files })
+    }
+
+    /// Checks the files of the artifact, which comes from a remote server:
+    /// they must be relative paths, which do not leave the target directory, and match the output paths of the target.
+    pub fn validate(&self, target_dir: &Path, output_paths: &Paths) -> Result<()> {
+        for file in &self.files {
+            if !is_safe_relative_path(&file.path)
+                || !output_paths.matches(&target_dir.join(&file.path))
+            {
+                return Err(anyhow::anyhow!(
+                    "Artifact contains {}, which is not an output path of the target",
+                    file.path.display()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the files of the artifact in `target_dir`, after checking all of them with `validate`.
+    pub fn write_files(&self, target_dir: &Path, output_paths: &Paths) -> Result<()> {
+        self.validate(target_dir, output_paths)?;
+
+        for file in &self.files {
+            let path = target_dir.join(&file.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            fs::write(&path, &file.content)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+
+            let mut permissions = fs::metadata(&path)
+                .with_context(|| format!("Failed to read metadata of {}", path.display()))?
+                .permissions();
+            restore_permissions(&mut permissions, file);
+            fs::set_permissions(&path, permissions)
+                .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the path is relative, and only made of normal components (no `..`).
+fn is_safe_relative_path(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        && path.components().next().is_some()
+}
+
+#[cfg(unix)]
+fn get_mode(permissions: &fs::Permissions) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(permissions.mode())
+}
+
+#[cfg(not(unix))]
+fn get_mode(_permissions: &fs::Permissions) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn restore_permissions(permissions: &mut fs::Permissions, file: &RemoteArtifactFile) {
+    use std::os::unix::fs::PermissionsExt;
+    // Only the permission bits are restored: an artifact must not create setuid or setgid files.
+    if let Some(mode) = file.mode {
+        permissions.set_mode(mode & 0o777);
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_permissions(permissions: &mut fs::Permissions, file: &RemoteArtifactFile) {
+    permissions.set_readonly(file.readonly);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemoteArtifact, RemoteArtifactFile};
+    use crate::paths::Paths;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_write_files_should_reject_paths_outside_of_output_paths() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let target_dir = temp_dir.path().join("target");
+        let output_paths = Paths::new(&target_dir, &["dist".to_string()]).unwrap();
+        let artifact = |path: &str| RemoteArtifact {
+            files: vec![RemoteArtifactFile {
+                path: PathBuf::from(path),
+                content: b"content".to_vec(),
+                readonly: false,
+                mode: None,
+            }],
+        };
+
+        for path in &["/etc/passwd", "dist/../../escaped", "src/main.rs", ""] {
+            artifact(path)
+                .validate(&target_dir, &output_paths)
+                .expect_err("Should reject a file which is not an output");
+            artifact(path)
+                .write_files(&target_dir, &output_paths)
+                .expect_err("Should reject a file which is not an output");
+        }
+        assert!(!target_dir.exists());
+
+        artifact("dist/index.js")
+            .write_files(&target_dir, &output_paths)
+            .expect("Should write an output file");
+        assert_eq!(
+            fs::read(target_dir.join("dist/index.js")).unwrap(),
+            b"content"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_permissions_should_drop_special_bits() {
+        use super::restore_permissions;
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = RemoteArtifactFile {
+            path: PathBuf::from("dist/run.sh"),
+            content: vec![],
+            readonly: false,
+            mode: Some(0o4755),
+        };
+        let mut permissions = fs::Permissions::from_mode(0o644);
+        restore_permissions(&mut permissions, &file);
+        assert_eq!(permissions.mode(), 0o755);
+    }
+}
@@ -1,91 +1,250 @@
 mod build_state;
 mod builder;
+pub mod command;
+pub mod events;
 pub mod incremental;
+mod process_group;
+mod reload;
 mod service;
+mod summary;
 mod watcher;
 
 use crate::domain::Target;
 use anyhow::{Context, Result};
 use build_state::TargetBuildStates;
 use builder::TargetBuilder;
+use command::OutputOptions;
+use crossbeam::channel::{after, never};
 use crossbeam::thread::Scope;
+use events::EventEmitter;
 use incremental::{IncrementalRunResult, IncrementalRunner};
+use reload::match_unchanged_targets;
 use service::ServicesRunner;
-use std::thread::sleep;
-use std::time::Duration;
-use watcher::TargetsWatcher;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use summary::BuildSummary;
+use watcher::{ConfigWatcher, TargetsWatcher};
+
+/// Window during which the changes of the configuration file are merged into a single reload.
+const CONFIG_RELOAD_DEBOUNCE: Duration = Duration::from_millis(100);
 
 pub struct Engine<'a> {
     targets: Vec<Target>,
     target_builder: TargetBuilder<'a>,
+    jobs: usize,
+    output_options: OutputOptions,
+    event_emitter: EventEmitter,
 }
 
 impl<'a> Engine<'a> {
-    pub fn new(targets: Vec<Target>, incremental_runner: IncrementalRunner<'a>) -> Self {
+    pub fn new(
+        targets: Vec<Target>,
+        incremental_runner: IncrementalRunner<'a>,
+        jobs: usize,
+        output_options: OutputOptions,
+        event_emitter: EventEmitter,
+    ) -> Self {
         Self {
             targets,
-            target_builder: TargetBuilder::new(incremental_runner),
+            target_builder: TargetBuilder::new(
+                incremental_runner,
+                output_options,
+                event_emitter.clone(),
+            ),
+            jobs,
+            output_options,
+            event_emitter,
         }
     }
 
-    pub fn watch(&'a self, scope: &Scope<'a>) -> Result<()> {
-        let watcher =
-            TargetsWatcher::new(&self.targets).with_context(|| "Failed to set up file watcher")?;
-
-        let mut services_runner = ServicesRunner::new(&self.targets);
-
-        let mut target_build_states = TargetBuildStates::new(&self.targets);
+    /// Builds the targets, and rebuilds them whenever their input files change.
+    ///
+    /// If `restart_builds` is set, builds whose inputs change while they run are cancelled and started over.
+    ///
+    /// When the configuration file changes, the targets are reloaded with `reload_targets`.
+    /// Only the targets whose definition changed are then rebuilt (along with their dependents),
+    /// and the services of the other targets keep running.
+    /// If the new configuration is invalid, the previous one is kept.
+    pub fn watch<F>(
+        &'a self,
+        scope: &Scope<'a>,
+        restart_builds: bool,
+        config_file: &Path,
+        reload_targets: F,
+    ) -> Result<()>
+    where
+        F: Fn() -> Result<Vec<Target>>,
+    {
+        let mut targets = self.targets.clone();
+        let mut watcher = TargetsWatcher::new(&targets, self.event_emitter.clone())
+            .with_context(|| "Failed to set up file watcher")?;
+        let config_watcher =
+            ConfigWatcher::new(config_file).with_context(|| "Failed to set up config watcher")?;
+
+        let mut services_runner =
+            ServicesRunner::new(&targets, self.output_options, self.event_emitter.clone());
+
+        let mut target_build_states = TargetBuildStates::new(&targets);
+        let mut config_reload_deadline = None;
+        let mut config_reload_requested = false;
 
         loop {
-            let invalidated_builds = watcher
-                .get_invalidated_targets()
-                .with_context(|| "File watch error")?;
-            target_build_states.set_builds_invalidated(&invalidated_builds);
+            // Builds are not started while waiting to reload the targets.
+            if !config_reload_requested {
+                self.build_ready_targets(scope, &targets, restart_builds, &mut target_build_states);
+            }
 
-            self.build_ready_targets(scope, &mut target_build_states);
+            let next_deadline = watcher
+                .get_next_deadline()
+                .into_iter()
+                .chain(config_reload_deadline)
+                .min();
+            let debounce_timeout = match next_deadline {
+                Some(deadline) => after(deadline.saturating_duration_since(Instant::now())),
+                None => never(),
+            };
+            let build_report_rx = target_build_states.rx.clone();
+
+            crossbeam::select! {
+                recv(watcher.rx) -> watch_event => {
+                    let watch_event = watch_event.with_context(|| "Watcher channel error")?;
+                    let pending_watch_events: Vec<_> = watcher.rx.try_iter().collect();
+                    for watch_event in std::iter::once(watch_event).chain(pending_watch_events) {
+                        watcher
+                            .add_watch_event(&targets, watch_event)
+                            .with_context(|| "File watch error")?;
+                    }
+                }
+                recv(config_watcher.rx) -> config_event => {
+                    config_event
+                        .with_context(|| "Config watcher channel error")?
+                        .with_context(|| "Config watcher received error")?;
+                    config_reload_deadline = Some(Instant::now() + CONFIG_RELOAD_DEBOUNCE);
+                }
+                recv(debounce_timeout) -> _ => {}
+                recv(build_report_rx) -> build_report => {
+                    let build_report = build_report.with_context(|| "Crossbeam parallelism failure")?;
+                    target_build_states.set_build_finished(&build_report);
+
+                    let target = &targets[build_report.target_id];
+                    if target_build_states.is_build_cancelled(target.id) {
+                        // The build is outdated: it will be started over.
+                    } else if let IncrementalRunResult::Run(Err(e)) = build_report.result {
+                        log::warn!("{} - Build failed: {}", target.name, e);
+                    } else {
+                        services_runner.restart_service(scope, target)?;
+                    }
+                }
+            }
 
-            if let Some(result) = target_build_states.get_finished_build()? {
-                let target = &self.targets[result.target_id];
-                if let IncrementalRunResult::Run(Err(e)) = result.result {
-                    log::warn!("{} - Build failed: {}", target.name, e);
-                } else {
-                    services_runner.restart_service(scope, target)?;
+            for target_id in watcher.take_invalidated_targets(&targets) {
+                target_build_states.set_build_invalidated(target_id);
+                if restart_builds {
+                    target_build_states
+                        .cancel_build(target_id)
+                        .with_context(|| "Failed to cancel build")?;
                 }
             }
 
-            sleep(Duration::from_millis(10))
+            if matches!(config_reload_deadline, Some(deadline) if deadline <= Instant::now()) {
+                config_reload_deadline = None;
+                config_reload_requested = true;
+            }
+
+            // Running builds refer to the current targets: the reload waits for them to finish.
+            if config_reload_requested && target_build_states.get_running_builds_count() == 0 {
+                config_reload_requested = false;
+                log::info!("Reloading {}", config_file.display());
+                match reload_targets() {
+                    Ok(reloaded_targets) => {
+                        let previous_target_ids =
+                            match_unchanged_targets(&targets, &reloaded_targets);
+                        watcher
+                            .reload(&reloaded_targets, &previous_target_ids)
+                            .with_context(|| "Failed to set up file watcher")?;
+                        services_runner.reload(&reloaded_targets, &previous_target_ids)?;
+                        target_build_states =
+                            target_build_states.reload(&reloaded_targets, &previous_target_ids);
+                        targets = reloaded_targets;
+                    }
+                    Err(e) => log::error!(
+                        "Failed to reload {}, keeping the previous configuration: {:#}",
+                        config_file.display(),
+                        e
+                    ),
+                }
+            }
         }
     }
 
-    pub fn build(&'a self, scope: &Scope<'a>) -> Result<()> {
+    /// Builds all the targets.
+    ///
+    /// Unless `keep_going` is set, no build is started after the first failure,
+    /// and the build stops once the running builds are finished.
+    /// Otherwise, all the targets which do not depend on a failed target are built.
+    /// Failures are recorded in the returned summary.
+    pub fn build(&'a self, scope: &Scope<'a>, keep_going: bool) -> Result<BuildSummary> {
         let mut target_build_states = TargetBuildStates::new(&self.targets);
+        let mut build_summary = BuildSummary::new(&self.targets);
+        let mut failed = false;
+
+        while !target_build_states.all_are_done() {
+            if !failed {
+                self.build_ready_targets(scope, &self.targets, false, &mut target_build_states);
+            } else if target_build_states.get_running_builds_count() == 0 {
+                break;
+            }
 
-        while !target_build_states.all_are_built() {
-            self.build_ready_targets(scope, &mut target_build_states);
-
-            if let Some(build_report) = target_build_states.get_finished_build()? {
-                if let IncrementalRunResult::Run(Err(e)) = build_report.result {
-                    let target = &self.targets[build_report.target_id];
-                    return Err(e.context(format!("Build failed for target {}", target.name)));
+            let build_report = target_build_states
+                .rx
+                .recv()
+                .with_context(|| "Crossbeam parallelism failure")?;
+            target_build_states.set_build_finished(&build_report);
+
+            if let IncrementalRunResult::Run(Err(e)) = &build_report.result {
+                let target = &self.targets[build_report.target_id];
+                if keep_going {
+                    log::error!("{} - Build failed: {:#}", target.name, e);
+                    target_build_states.set_dependents_blocked(target.id);
+                } else {
+                    failed = true;
                 }
             }
 
-            sleep(Duration::from_millis(10))
+            build_summary.add_build_report(build_report);
         }
 
-        Ok(())
+        build_summary.set_blocked_targets(&target_build_states.get_blocked_targets());
+        build_summary.finish();
+
+        Ok(build_summary)
     }
 
     fn build_ready_targets(
         &'a self,
         scope: &Scope<'a>,
+        targets: &[Target],
+        cancellable: bool,
         target_build_states: &mut TargetBuildStates,
     ) {
-        for &target_id in &target_build_states.get_ready_to_build_targets() {
-            let target = self.targets.get(target_id).unwrap();
+        let available_jobs = self
+            .jobs
+            .saturating_sub(target_build_states.get_running_builds_count());
+
+        for &target_id in target_build_states
+            .get_ready_to_build_targets()
+            .iter()
+            .take(available_jobs)
+        {
+            let target = &targets[target_id];
             target_build_states.set_build_started(target.id);
-            self.target_builder
-                .build(scope, target, &target_build_states.tx);
+            self.target_builder.build(
+                scope,
+                target,
+                cancellable,
+                target_build_states.get_build_cancellation(target.id),
+                &target_build_states.tx,
+            );
         }
     }
 }
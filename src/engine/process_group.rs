@@ -0,0 +1,136 @@
+//! Process groups of the build commands which can be cancelled.
+//!
+//! A command started in its own process group can be killed along with all the processes it started
+//! (e.g. a compiler started by the shell). As such commands no longer receive the signals sent to the
+//! process group of `zinoma` (e.g. on Ctrl-C), these signals are forwarded to them.
+//!
+//! Process groups are only available on Unix. Elsewhere, commands are started as usual,
+//! and killing them only kills the command itself.
+
+use anyhow::Result;
+use duct::Expression;
+
+/// Makes the command start in its own process group.
+#[cfg(unix)]
+pub fn configure(expression: &Expression) -> Expression {
+    use std::os::unix::process::CommandExt;
+    expression.before_spawn(|command| {
+        command.process_group(0);
+        Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+pub fn configure(expression: &Expression) -> Expression {
+    expression.clone()
+}
+
+/// Process group of a running command, to which the signals received by `zinoma` are forwarded until it is dropped.
+pub struct ProcessGroup {
+    #[cfg(unix)]
+    pgid: libc::pid_t,
+}
+
+#[cfg(unix)]
+impl ProcessGroup {
+    /// Registers the process group led by the process `pid`, started with [`configure`].
+    pub fn register(pid: u32) -> Self {
+        let pgid = pid as libc::pid_t;
+        unix::register(pgid);
+        Self { pgid }
+    }
+
+    pub fn kill(&self) -> Result<()> {
+        // The group no longer exists once all of its processes ended.
+        if unsafe { libc::killpg(self.pgid, libc::SIGKILL) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ESRCH) {
+                return Err(anyhow::Error::new(error)
+                    .context(format!("Failed to kill process group {}", self.pgid)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        unix::unregister(self.pgid);
+    }
+}
+
+#[cfg(not(unix))]
+impl ProcessGroup {
+    pub fn register(_pid: u32) -> Self {
+        Self {}
+    }
+
+    pub fn kill(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Once;
+
+    /// The signal handler cannot allocate nor lock: the process groups are kept in a fixed array of atomics.
+    const MAX_PROCESS_GROUPS: usize = 1024;
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_PROCESS_GROUP: AtomicI32 = AtomicI32::new(0);
+    static PROCESS_GROUPS: [AtomicI32; MAX_PROCESS_GROUPS] = [NO_PROCESS_GROUP; MAX_PROCESS_GROUPS];
+
+    static FORWARDED_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+    static INSTALL_SIGNAL_HANDLERS: Once = Once::new();
+
+    pub fn register(pgid: libc::pid_t) {
+        INSTALL_SIGNAL_HANDLERS.call_once(install_signal_handlers);
+        let registered = PROCESS_GROUPS.iter().any(|slot| {
+            slot.compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        if !registered {
+            log::warn!(
+                "Too many running commands: signals will not be forwarded to process group {}",
+                pgid
+            );
+        }
+    }
+
+    pub fn unregister(pgid: libc::pid_t) {
+        for slot in PROCESS_GROUPS.iter() {
+            if slot
+                .compare_exchange(pgid, 0, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+
+    fn install_signal_handlers() {
+        for &signal in FORWARDED_SIGNALS.iter() {
+            let handler = forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            // Signals ignored by `zinoma` (e.g. with `nohup`) stay ignored.
+            if unsafe { libc::signal(signal, handler) } == libc::SIG_IGN {
+                unsafe { libc::signal(signal, libc::SIG_IGN) };
+            }
+        }
+    }
+
+    /// Forwards the signal to the registered process groups, then lets it apply to `zinoma` as usual.
+    extern "C" fn forward_signal(signal: libc::c_int) {
+        for slot in PROCESS_GROUPS.iter() {
+            let pgid = slot.load(Ordering::SeqCst);
+            if pgid != 0 {
+                unsafe { libc::killpg(pgid, signal) };
+            }
+        }
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
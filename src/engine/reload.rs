@@ -0,0 +1,76 @@
+use super::incremental::compute_target_definition_fingerprint;
+use crate::domain::{Target, TargetId};
+use std::collections::HashMap;
+
+/// Matches the reloaded targets with the previous targets whose definition did not change.
+///
+/// Returns, for each reloaded target, the id of the identical previous target (if any).
+pub fn match_unchanged_targets(
+    previous_targets: &[Target],
+    targets: &[Target],
+) -> Vec<Option<TargetId>> {
+    let previous_targets_by_name: HashMap<_, _> = previous_targets
+        .iter()
+        .map(|target| (target.name.as_str(), target))
+        .collect();
+    let get_dependency_names = |targets: &[Target], target: &Target| {
+        let mut dependency_names: Vec<_> = target
+            .dependencies
+            .iter()
+            .map(|&dependency_id| targets[dependency_id].name.clone())
+            .collect();
+        dependency_names.sort();
+        dependency_names
+    };
+
+    targets
+        .iter()
+        .map(|target| {
+            previous_targets_by_name
+                .get(target.name.as_str())
+                .filter(|previous_target| {
+                    compute_target_definition_fingerprint(previous_target)
+                        == compute_target_definition_fingerprint(target)
+                        && get_dependency_names(previous_targets, previous_target)
+                            == get_dependency_names(targets, target)
+                })
+                .map(|previous_target| previous_target.id)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_unchanged_targets;
+    use crate::domain::tests::build_target;
+    use crate::domain::Target;
+
+    #[test]
+    fn test_match_unchanged_targets() {
+        let build_named_target = |id, name: &str, dependencies| Target {
+            name: name.to_string(),
+            build_list: vec![format!("echo {}", name)],
+            ..build_target(id, dependencies)
+        };
+        let previous_targets = vec![
+            build_named_target(0, "removed", vec![]),
+            build_named_target(1, "codegen", vec![]),
+            build_named_target(2, "compile", vec![1]),
+            build_named_target(3, "test", vec![2]),
+        ];
+        let targets = vec![
+            build_named_target(0, "codegen", vec![]),
+            build_named_target(1, "compile", vec![0]),
+            build_named_target(2, "lint", vec![]),
+            Target {
+                build_list: vec!["echo modified".to_string()],
+                ..build_named_target(3, "test", vec![1])
+            },
+        ];
+
+        assert_eq!(
+            match_unchanged_targets(&previous_targets, &targets),
+            vec![Some(1), Some(2), None, None]
+        );
+    }
+}
@@ -1,21 +1,30 @@
-use crate::domain::Target;
+use super::command::{build_command, start_command, OutputOptions};
+use super::events::{Event, EventEmitter};
+use crate::domain::{Target, TargetId};
 use anyhow::{Context, Result};
 use crossbeam::channel::{unbounded, Receiver, Sender};
 use crossbeam::thread::Scope;
-use duct::cmd;
 
 pub struct ServicesRunner {
     tx_channels: Vec<Option<Sender<RunSignal>>>,
+    output_options: OutputOptions,
+    event_emitter: EventEmitter,
 }
 
 impl ServicesRunner {
-    pub fn new(targets: &[Target]) -> Self {
+    pub fn new(
+        targets: &[Target],
+        output_options: OutputOptions,
+        event_emitter: EventEmitter,
+    ) -> Self {
         Self {
             tx_channels: vec![None; targets.len()],
+            output_options,
+            event_emitter,
         }
     }
 
-    pub fn restart_service<'a>(&mut self, scope: &Scope<'a>, target: &'a Target) -> Result<()> {
+    pub fn restart_service(&mut self, scope: &Scope, target: &Target) -> Result<()> {
         if target.service.is_some() {
             // If already running, send a kill signal.
             if let Some(service_tx) = &self.tx_channels[target.id] {
@@ -27,21 +36,59 @@ impl ServicesRunner {
             let (service_tx, service_rx) = unbounded();
             self.tx_channels[target.id] = Some(service_tx);
 
-            scope.spawn(move |_| run_target_service(target, service_rx).unwrap());
+            let output_options = self.output_options;
+            let event_emitter = self.event_emitter.clone();
+            let target = target.clone();
+            scope.spawn(move |_| {
+                run_target_service(&target, &output_options, &event_emitter, service_rx).unwrap()
+            });
         }
 
         Ok(())
     }
+
+    /// Keeps the services of the reloaded targets which did not change running, and stops the others.
+    pub fn reload(
+        &mut self,
+        targets: &[Target],
+        previous_target_ids: &[Option<TargetId>],
+    ) -> Result<()> {
+        let mut tx_channels = vec![None; targets.len()];
+        for (target_id, previous_target_id) in previous_target_ids.iter().enumerate() {
+            if let Some(previous_target_id) = *previous_target_id {
+                tx_channels[target_id] = self.tx_channels[previous_target_id].take();
+            }
+        }
+
+        for service_tx in self.tx_channels.iter().flatten() {
+            service_tx
+                .send(RunSignal::Kill)
+                .with_context(|| "Failed to send Kill signal to running process")?;
+        }
+        self.tx_channels = tx_channels;
+
+        Ok(())
+    }
 }
 
-fn run_target_service(target: &Target, rx: Receiver<RunSignal>) -> Result<()> {
+fn run_target_service(
+    target: &Target,
+    output_options: &OutputOptions,
+    event_emitter: &EventEmitter,
+    rx: Receiver<RunSignal>,
+) -> Result<()> {
     if let Some(command) = &target.service {
         log::info!("{} - Command: \"{}\" - Run", target.name, command);
-        let handle = cmd!("/bin/sh", "-c", command)
-            .dir(&target.path)
-            .stderr_to_stdout()
-            .start()
-            .with_context(|| format!("Failed to run command: {}", command))?;
+        let handle = start_command(
+            &build_command(target, command),
+            output_options,
+            &output_options.get_prefix(target),
+        )
+        .with_context(|| format!("Failed to run command: {}", command))?;
+        event_emitter.emit(Event::ServiceStarted {
+            target: target.name.to_string(),
+            command: command.to_string(),
+        });
 
         match rx.recv().with_context(|| "Receiver error")? {
             RunSignal::Kill => {
@@ -49,6 +96,9 @@ fn run_target_service(target: &Target, rx: Receiver<RunSignal>) -> Result<()> {
                 handle
                     .kill()
                     .with_context(|| format!("Failed to kill process {}", command))?;
+                event_emitter.emit(Event::ServiceStopped {
+                    target: target.name.to_string(),
+                });
             }
         }
     }
@@ -0,0 +1,270 @@
+use super::builder::{BuildReport, CommandReport};
+use super::incremental::IncrementalRunResult;
+use crate::domain::{Target, TargetId};
+use anyhow::{Error, Result};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Outcome of every target of a build, meant to be displayed at the end of the run.
+pub struct BuildSummary {
+    start: Instant,
+    wall_time: Duration,
+    target_summaries: Vec<TargetSummary>,
+}
+
+struct TargetSummary {
+    name: String,
+    outcome: TargetOutcome,
+    duration: Option<Duration>,
+    commands: Vec<CommandReport>,
+}
+
+enum TargetOutcome {
+    Built,
+    Skipped,
+    Restored,
+    Failed(Error),
+    NotRun,
+    Blocked(TargetId),
+}
+
+impl BuildSummary {
+    pub fn new(targets: &[Target]) -> Self {
+        Self {
+            start: Instant::now(),
+            wall_time: Duration::default(),
+            target_summaries: targets
+                .iter()
+                .map(|target| TargetSummary {
+                    name: target.name.clone(),
+                    outcome: TargetOutcome::NotRun,
+                    duration: None,
+                    commands: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    pub fn add_build_report(&mut self, build_report: BuildReport) {
+        let target_summary = &mut self.target_summaries[build_report.target_id];
+        target_summary.outcome = match build_report.result {
+            IncrementalRunResult::Skipped => TargetOutcome::Skipped,
+            IncrementalRunResult::Restored => TargetOutcome::Restored,
+            IncrementalRunResult::Run(Ok(_)) => TargetOutcome::Built,
+            IncrementalRunResult::Run(Err(e)) => TargetOutcome::Failed(e),
+        };
+        target_summary.duration = Some(build_report.duration);
+        target_summary.commands = build_report.commands;
+    }
+
+    pub fn set_blocked_targets(&mut self, blocked_targets: &[(TargetId, TargetId)]) {
+        for &(target_id, failed_target_id) in blocked_targets {
+            self.target_summaries[target_id].outcome = TargetOutcome::Blocked(failed_target_id);
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.wall_time = self.start.elapsed();
+    }
+
+    /// Converts the summary into an error if any target failed.
+    pub fn into_result(self) -> Result<()> {
+        let target_names: Vec<_> = self
+            .target_summaries
+            .iter()
+            .map(|target_summary| target_summary.name.clone())
+            .collect();
+
+        let mut failures = Vec::new();
+        let mut blocked_targets = Vec::new();
+        for target_summary in self.target_summaries {
+            match target_summary.outcome {
+                TargetOutcome::Failed(e) => failures.push((target_summary.name, e)),
+                TargetOutcome::Blocked(failed_target_id) => {
+                    blocked_targets.push((target_summary.name, &target_names[failed_target_id]))
+                }
+                _ => {}
+            }
+        }
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        if failures.len() == 1 && blocked_targets.is_empty() {
+            let (target_name, e) = failures.remove(0);
+            return Err(e.context(format!("Build failed for target {}", target_name)));
+        }
+
+        let mut description = format!("Build failed for {} target(s):", failures.len());
+        for (target_name, e) in failures {
+            description += &format!("\n- {}: {:#}", target_name, e);
+        }
+
+        if !blocked_targets.is_empty() {
+            description += "\nTargets not built because of failures:";
+            for (target_name, failed_target_name) in blocked_targets {
+                description += &format!(
+                    "\n- {} (not built because of {})",
+                    target_name, failed_target_name
+                );
+            }
+        }
+
+        Err(anyhow::anyhow!(description))
+    }
+
+    fn describe_outcome(&self, outcome: &TargetOutcome) -> String {
+        match outcome {
+            TargetOutcome::Built => "built".to_string(),
+            TargetOutcome::Skipped => "skipped (not modified)".to_string(),
+            TargetOutcome::Restored => "restored from cache".to_string(),
+            TargetOutcome::Failed(_) => "failed".to_string(),
+            TargetOutcome::NotRun => "not run".to_string(),
+            TargetOutcome::Blocked(failed_target_id) => format!(
+                "not run ({} failed)",
+                self.target_summaries[*failed_target_id].name
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BuildSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outcomes: Vec<_> = self
+            .target_summaries
+            .iter()
+            .map(|target_summary| self.describe_outcome(&target_summary.outcome))
+            .collect();
+
+        let name_width = self
+            .target_summaries
+            .iter()
+            .flat_map(|target_summary| {
+                let command_widths = target_summary
+                    .commands
+                    .iter()
+                    .map(|command_report| command_report.command.len() + 2);
+                std::iter::once(target_summary.name.len()).chain(command_widths)
+            })
+            .chain(std::iter::once("TARGET".len()))
+            .max()
+            .unwrap();
+        let outcome_width = outcomes
+            .iter()
+            .map(String::len)
+            .chain(std::iter::once("OUTCOME".len()))
+            .max()
+            .unwrap();
+
+        writeln!(f, "Build summary (took: {}ms)", self.wall_time.as_millis())?;
+        writeln!(
+            f,
+            "{:name_width$}  {:outcome_width$}  DURATION",
+            "TARGET",
+            "OUTCOME",
+            name_width = name_width,
+            outcome_width = outcome_width
+        )?;
+
+        for (target_summary, outcome) in self.target_summaries.iter().zip(outcomes) {
+            writeln!(
+                f,
+                "{:name_width$}  {:outcome_width$}  {}",
+                target_summary.name,
+                outcome,
+                format_duration(target_summary.duration),
+                name_width = name_width,
+                outcome_width = outcome_width
+            )?;
+
+            for command_report in &target_summary.commands {
+                writeln!(
+                    f,
+                    "  {:name_width$}  {:outcome_width$}  {}",
+                    command_report.command,
+                    if command_report.succeeded {
+                        ""
+                    } else {
+                        "failed"
+                    },
+                    format_duration(Some(command_report.duration)),
+                    name_width = name_width - 2,
+                    outcome_width = outcome_width
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("{}ms", duration.as_millis()),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BuildSummary;
+    use crate::domain::tests::build_target;
+    use crate::domain::Target;
+    use crate::engine::builder::{BuildReport, CommandReport};
+    use crate::engine::incremental::IncrementalRunResult;
+    use std::time::Duration;
+
+    #[test]
+    fn test_summary_without_failure_is_ok() {
+        let mut build_summary = BuildSummary::new(&build_targets(2));
+        build_summary.add_build_report(build_report(0, IncrementalRunResult::Skipped));
+        build_summary.add_build_report(build_report(1, IncrementalRunResult::Run(Ok(()))));
+
+        build_summary
+            .into_result()
+            .expect("A summary without failure should be ok");
+    }
+
+    #[test]
+    fn test_summary_lists_failed_and_blocked_targets() {
+        let mut build_summary = BuildSummary::new(&build_targets(3));
+        build_summary.add_build_report(build_report(
+            0,
+            IncrementalRunResult::Run(Err(anyhow::anyhow!("Command failed"))),
+        ));
+        build_summary.set_blocked_targets(&[(2, 0)]);
+
+        let display = build_summary.to_string();
+        let lines: Vec<_> = display.lines().collect();
+        assert!(lines[2].starts_with("target_0") && lines[2].contains("failed"));
+        assert!(lines[3].starts_with("  my command"));
+        assert!(lines[4].starts_with("target_1") && lines[4].contains("not run"));
+        assert!(lines[5].starts_with("target_2") && lines[5].contains("not run (target_0 failed)"));
+
+        let error = build_summary
+            .into_result()
+            .expect_err("A summary with failures should be an error");
+        assert!(format!("{:#}", error).contains("target_2 (not built because of target_0)"));
+    }
+
+    fn build_report(
+        target_id: usize,
+        result: IncrementalRunResult<anyhow::Result<()>>,
+    ) -> BuildReport {
+        BuildReport::new(
+            target_id,
+            result,
+            Duration::from_millis(12),
+            vec![CommandReport {
+                command: "my command".to_string(),
+                duration: Duration::from_millis(10),
+                succeeded: true,
+            }],
+        )
+    }
+
+    fn build_targets(count: usize) -> Vec<Target> {
+        (0..count).map(|id| build_target(id, vec![])).collect()
+    }
+}
@@ -1,98 +1,290 @@
+use super::events::{self, EventEmitter};
 use crate::domain::{Target, TargetId};
 use anyhow::{Context, Error, Result};
-use crossbeam::channel::{unbounded, Receiver, TryRecvError};
+use crossbeam::channel::{unbounded, Receiver};
 use notify::{ErrorKind, Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-pub struct TargetsWatcher<'a> {
-    target_watchers: Vec<TargetWatcher<'a>>,
+/// Watches the input paths of all the targets with a single file system watcher.
+///
+/// Each directory is watched once, even if it is an input of several targets.
+pub struct TargetsWatcher {
+    event_emitter: EventEmitter,
+    watcher: RecommendedWatcher,
+    /// Directories and files currently watched (recursively).
+    watched_paths: BTreeSet<PathBuf>,
+    targets_index: TargetsIndex,
+    pub rx: Receiver<notify::Result<Event>>,
+    /// Invalidations waiting for the end of the debounce window of their target.
+    pending_invalidations: BTreeMap<TargetId, PendingInvalidation>,
 }
 
-impl<'a> TargetsWatcher<'a> {
-    pub fn new(targets: &'a [Target]) -> Result<Self> {
-        let mut target_watchers = Vec::new();
-        for target in targets.iter() {
-            target_watchers.push(TargetWatcher::new(target)?);
-        }
-        Ok(Self { target_watchers })
+/// Maximum duration of a debounce window, as a multiple of the debounce duration of the target.
+///
+/// It ensures that a continuous stream of events (e.g. a log file written within the inputs) does not postpone the build forever.
+const MAX_DEBOUNCE_WINDOW_FACTOR: u32 = 4;
+
+struct PendingInvalidation {
+    first_event: Instant,
+    deadline: Instant,
+    paths: BTreeSet<PathBuf>,
+}
+
+impl TargetsWatcher {
+    pub fn new(targets: &[Target], event_emitter: EventEmitter) -> Result<Self> {
+        let (tx, rx) = unbounded();
+        let watcher: RecommendedWatcher =
+            Watcher::new_immediate(move |e| tx.send(e).with_context(|| "Sender error").unwrap())
+                .with_context(|| "Error creating watcher")?;
+
+        let mut targets_watcher = Self {
+            event_emitter,
+            watcher,
+            watched_paths: BTreeSet::new(),
+            targets_index: TargetsIndex::new(targets),
+            rx,
+            pending_invalidations: BTreeMap::new(),
+        };
+        targets_watcher.update_watched_paths()?;
+        Ok(targets_watcher)
     }
 
-    pub fn get_invalidated_targets(&self) -> Result<Vec<TargetId>> {
-        let mut invalidated_targets = Vec::new();
+    /// Watches the reloaded targets instead of the previous ones.
+    ///
+    /// Only the paths which are no longer inputs are unwatched, and only the new input paths are watched.
+    /// The pending invalidations of the targets which did not change are kept.
+    pub fn reload(
+        &mut self,
+        targets: &[Target],
+        previous_target_ids: &[Option<TargetId>],
+    ) -> Result<()> {
+        self.targets_index = TargetsIndex::new(targets);
+        self.update_watched_paths()?;
 
-        for (target_id, target_watcher) in self.target_watchers.iter().enumerate() {
-            if target_watcher.is_invalidated()? {
-                invalidated_targets.push(target_id);
+        let mut pending_invalidations = BTreeMap::new();
+        for (target_id, previous_target_id) in previous_target_ids.iter().enumerate() {
+            if let Some(pending_invalidation) = previous_target_id.and_then(|previous_target_id| {
+                self.pending_invalidations.remove(&previous_target_id)
+            }) {
+                pending_invalidations.insert(target_id, pending_invalidation);
             }
         }
+        self.pending_invalidations = pending_invalidations;
 
-        Ok(invalidated_targets)
+        Ok(())
     }
-}
 
-pub struct TargetWatcher<'a> {
-    target: &'a Target,
-    rx: Receiver<notify::Result<Event>>,
-    _watcher: RecommendedWatcher,
-}
+    fn update_watched_paths(&mut self) -> Result<()> {
+        let paths_to_watch = self.targets_index.get_top_level_roots();
 
-impl<'a> TargetWatcher<'a> {
-    pub fn new(target: &'a Target) -> Result<Self> {
-        let (tx, rx) = unbounded();
-        let mut watcher: RecommendedWatcher =
-            Watcher::new_immediate(move |e| tx.send(e).with_context(|| "Sender error").unwrap())
-                .with_context(|| "Error creating watcher")?;
+        for path in self.watched_paths.difference(&paths_to_watch) {
+            if let Err(e) = self.watcher.unwatch(path) {
+                log::debug!("Failed to unwatch {}: {}", path.display(), e);
+            }
+        }
 
-        for path in &target.input_paths {
-            match watcher.watch(path, RecursiveMode::Recursive) {
-                Ok(_) => {}
-                Err(notify::Error {
-                    kind: ErrorKind::PathNotFound,
-                    ..
-                }) => {
-                    log::warn!(
-                        "{} - Skipping watch on non-existing path: {}",
-                        target.name,
-                        path.display(),
-                    );
+        let mut watched_paths = BTreeSet::new();
+        for path in paths_to_watch {
+            if self.watched_paths.contains(&path) {
+                watched_paths.insert(path);
+                continue;
+            }
+
+            match self.watcher.watch(&path, RecursiveMode::Recursive) {
+                Ok(_) => {
+                    watched_paths.insert(path);
+                }
+                Err(e) if is_path_not_found(&e) => {
+                    log::warn!("Skipping watch on non-existing path: {}", path.display());
                 }
                 Err(e) => {
-                    return Err(Error::new(e).context(format!(
-                        "Error watching path {} for target {}",
-                        path.display(),
-                        target.name,
-                    )));
+                    return Err(
+                        Error::new(e).context(format!("Error watching path {}", path.display()))
+                    );
                 }
             }
         }
+        self.watched_paths = watched_paths;
 
-        Ok(Self {
-            target,
-            rx,
-            _watcher: watcher,
-        })
+        Ok(())
     }
 
-    pub fn is_invalidated(&self) -> Result<bool> {
-        match self.rx.try_recv() {
-            Ok(event) => {
-                let paths: Vec<_> = event
-                    .unwrap()
-                    .paths
-                    .into_iter()
-                    .filter(|path| !is_tmp_editor_file(path))
-                    .collect();
-
-                let invalidated = !paths.is_empty();
-                if invalidated {
-                    log::trace!("{} - Invalidated by {:?}", self.target.name, paths)
+    /// Records the paths of the event in the pending invalidations of the targets they are inputs of.
+    ///
+    /// The invalidation of a target is delayed until no other event is received for this target during its debounce window,
+    /// and at most `MAX_DEBOUNCE_WINDOW_FACTOR` times its debounce duration after the first event.
+    pub fn add_watch_event(
+        &mut self,
+        targets: &[Target],
+        event: notify::Result<Event>,
+    ) -> Result<()> {
+        let event = event.with_context(|| "Watcher received error")?;
+        let mut paths_by_target: BTreeMap<TargetId, Vec<PathBuf>> = BTreeMap::new();
+        for path in event
+            .paths
+            .into_iter()
+            .filter(|path| !is_tmp_editor_file(path))
+        {
+            for target_id in self.targets_index.get_targets(&path) {
+                targets[target_id].input_paths.reload_ignore_files(&path);
+                if targets[target_id].input_paths.matches(&path) {
+                    paths_by_target
+                        .entry(target_id)
+                        .or_default()
+                        .push(path.clone());
                 }
+            }
+        }
+
+        let now = Instant::now();
+        for (target_id, paths) in paths_by_target {
+            let watch_debounce = targets[target_id].watch_debounce;
+            let pending_invalidation =
+                self.pending_invalidations
+                    .entry(target_id)
+                    .or_insert_with(|| PendingInvalidation {
+                        first_event: now,
+                        deadline: now,
+                        paths: BTreeSet::new(),
+                    });
+            pending_invalidation.deadline = (now + watch_debounce).min(
+                pending_invalidation.first_event + watch_debounce * MAX_DEBOUNCE_WINDOW_FACTOR,
+            );
+            pending_invalidation.paths.extend(paths);
+        }
+        Ok(())
+    }
+
+    /// Returns the earliest end of the debounce windows, if some invalidations are pending.
+    pub fn get_next_deadline(&self) -> Option<Instant> {
+        self.pending_invalidations
+            .values()
+            .map(|pending_invalidation| pending_invalidation.deadline)
+            .min()
+    }
+
+    /// Returns the targets whose debounce window is over, along with all the paths which changed during the window.
+    pub fn take_invalidated_targets(&mut self, targets: &[Target]) -> Vec<TargetId> {
+        let now = Instant::now();
+        let target_ids: Vec<_> = self
+            .pending_invalidations
+            .iter()
+            .filter(|(_, pending_invalidation)| pending_invalidation.deadline <= now)
+            .map(|(&target_id, _)| target_id)
+            .collect();
+
+        for &target_id in &target_ids {
+            let target = &targets[target_id];
+            let paths = self.pending_invalidations.remove(&target_id).unwrap().paths;
+            log::trace!("{} - Invalidated by {:?}", target.name, paths);
+            self.event_emitter.emit(events::Event::WatchInvalidated {
+                target: target.name.to_string(),
+                paths: paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+            });
+        }
+
+        target_ids
+    }
+}
+
+/// Indexes the targets by the roots of their input paths.
+struct TargetsIndex {
+    targets_by_root: HashMap<PathBuf, BTreeSet<TargetId>>,
+}
+
+impl TargetsIndex {
+    fn new(targets: &[Target]) -> Self {
+        let mut targets_by_root: HashMap<PathBuf, BTreeSet<TargetId>> = HashMap::new();
+        for target in targets {
+            for root in target.input_paths.get_roots() {
+                targets_by_root
+                    .entry(root.to_path_buf())
+                    .or_default()
+                    .insert(target.id);
+            }
+        }
+        Self { targets_by_root }
+    }
 
-                Ok(invalidated)
+    /// Returns the targets having an input root which is the path itself, or one of its ancestors.
+    fn get_targets(&self, path: &Path) -> BTreeSet<TargetId> {
+        path.ancestors()
+            .filter_map(|ancestor| self.targets_by_root.get(ancestor))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Returns the roots which are not contained in another root.
+    fn get_top_level_roots(&self) -> BTreeSet<PathBuf> {
+        let mut top_level_roots = BTreeSet::new();
+        // Sorted paths come after their ancestors.
+        let roots: BTreeSet<_> = self.targets_by_root.keys().collect();
+        for root in roots {
+            if !root
+                .ancestors()
+                .skip(1)
+                .any(|ancestor| top_level_roots.contains(ancestor))
+            {
+                top_level_roots.insert(root.to_path_buf());
             }
-            Err(TryRecvError::Empty) => Ok(false),
-            Err(e) => Err(Error::new(e).context("Watcher received error")),
         }
+        top_level_roots
+    }
+}
+
+fn is_path_not_found(e: &notify::Error) -> bool {
+    match &e.kind {
+        ErrorKind::PathNotFound => true,
+        ErrorKind::Io(e) => e.kind() == io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Watches the configuration file of the project.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+    pub rx: Receiver<notify::Result<Event>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(config_file: &Path) -> Result<Self> {
+        let (tx, rx) = unbounded();
+        let config_file_name = config_file.file_name().map(|name| name.to_os_string());
+        let mut watcher: RecommendedWatcher =
+            Watcher::new_immediate(move |e: notify::Result<Event>| {
+                let is_config_file_event = match &e {
+                    Ok(event) => event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == config_file_name.as_deref()),
+                    Err(_) => true,
+                };
+                if is_config_file_event {
+                    tx.send(e).with_context(|| "Sender error").unwrap()
+                }
+            })
+            .with_context(|| "Error creating watcher")?;
+
+        // Editors often replace the file instead of modifying it: its directory is watched instead.
+        let config_dir = match config_file.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        watcher
+            .watch(config_dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Error watching {}", config_file.display()))?;
+
+        Ok(Self {
+            _watcher: watcher,
+            rx,
+        })
     }
 }
 
@@ -135,3 +327,128 @@ mod is_tmp_editor_file_tests {
         assert!(is_tmp_editor_file(path));
     }
 }
+
+#[cfg(test)]
+mod targets_watcher_tests {
+    use super::{TargetsIndex, TargetsWatcher};
+    use crate::domain::tests::build_target;
+    use crate::domain::Target;
+    use crate::engine::events::EventEmitter;
+    use crate::paths::Paths;
+    use notify::{Event, EventKind};
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn test_targets_index_routes_paths_to_the_targets_of_their_roots() {
+        // The project directory is absolute on every platform, but does not need to exist.
+        let project_dir = std::env::temp_dir().join("project");
+        let project_path = |path: &str| {
+            path.split('/')
+                .fold(project_dir.clone(), |project_path, component| {
+                    project_path.join(component)
+                })
+        };
+        let build_watched_target = |id, input_paths: &[&str]| {
+            let input_paths: Vec<_> = input_paths.iter().map(|path| path.to_string()).collect();
+            Target {
+                input_paths: Paths::new(&project_dir, &input_paths).unwrap(),
+                ..build_target(id, vec![])
+            }
+        };
+        let targets = vec![
+            build_watched_target(0, &["src"]),
+            build_watched_target(1, &["src/generated", "lib/**/*.rs"]),
+            build_watched_target(2, &["README.md"]),
+        ];
+
+        let targets_index = TargetsIndex::new(&targets);
+
+        let get_targets = |path| {
+            let target_ids: Vec<_> = targets_index
+                .get_targets(&project_path(path))
+                .into_iter()
+                .collect();
+            target_ids
+        };
+        assert_eq!(get_targets("src/generated/schema.rs"), vec![0, 1]);
+        assert_eq!(get_targets("src/main.rs"), vec![0]);
+        assert_eq!(get_targets("lib/deep/lib.rs"), vec![1]);
+        assert_eq!(get_targets("README.md"), vec![2]);
+        assert!(get_targets("Cargo.toml").is_empty());
+
+        let top_level_roots: Vec<_> = targets_index.get_top_level_roots().into_iter().collect();
+        assert_eq!(
+            top_level_roots,
+            vec![
+                project_path("README.md"),
+                project_path("lib"),
+                project_path("src"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events_are_merged_until_the_end_of_the_debounce_window() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let project_dir = project_dir.path();
+        fs::create_dir_all(project_dir.join("src")).unwrap();
+        let build_watched_target = |id, watch_debounce| Target {
+            input_paths: Paths::new(project_dir, &["src".to_string()]).unwrap(),
+            watch_debounce,
+            ..build_target(id, vec![])
+        };
+        let targets = vec![
+            build_watched_target(0, Duration::default()),
+            build_watched_target(1, Duration::from_secs(3600)),
+        ];
+        let mut watcher = TargetsWatcher::new(&targets, EventEmitter::disabled()).unwrap();
+
+        for file_name in &["src/main.rs", "src/lib.rs", "src/.main.rs.swp", "README.md"] {
+            let event = Event::new(EventKind::Any).add_path(project_dir.join(file_name));
+            watcher.add_watch_event(&targets, Ok(event)).unwrap();
+        }
+
+        let paths: Vec<_> = watcher.pending_invalidations[&1]
+            .paths
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                project_dir.join("src/lib.rs"),
+                project_dir.join("src/main.rs")
+            ]
+        );
+        assert_eq!(watcher.take_invalidated_targets(&targets), vec![0]);
+        assert!(watcher.take_invalidated_targets(&targets).is_empty());
+        assert!(watcher.get_next_deadline().is_some());
+    }
+
+    #[test]
+    fn test_debounce_window_is_capped_after_the_first_event() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let project_dir = project_dir.path();
+        fs::create_dir_all(project_dir.join("src")).unwrap();
+        let targets = vec![Target {
+            input_paths: Paths::new(project_dir, &["src".to_string()]).unwrap(),
+            watch_debounce: Duration::from_secs(1),
+            ..build_target(0, vec![])
+        }];
+        let mut watcher = TargetsWatcher::new(&targets, EventEmitter::disabled()).unwrap();
+        let add_event = |watcher: &mut TargetsWatcher| {
+            let event = Event::new(EventKind::Any).add_path(project_dir.join("src/app.log"));
+            watcher.add_watch_event(&targets, Ok(event)).unwrap();
+        };
+
+        add_event(&mut watcher);
+        assert!(watcher.take_invalidated_targets(&targets).is_empty());
+
+        // Events keep coming, long after the first one.
+        let pending_invalidation = watcher.pending_invalidations.get_mut(&0).unwrap();
+        pending_invalidation.first_event -= Duration::from_secs(4);
+        add_event(&mut watcher);
+        assert_eq!(watcher.take_invalidated_targets(&targets), vec![0]);
+    }
+}
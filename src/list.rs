@@ -0,0 +1,41 @@
+use crate::config::Config;
+
+/// Prints the targets of the project, along with their descriptions and dependencies.
+///
+/// Targets whose names start with `_` are internal, and are only printed when `show_all` is set.
+pub fn print_target_list(config: &Config, show_all: bool) {
+    let mut targets: Vec<_> = config
+        .get_targets()
+        .iter()
+        .filter(|(target_name, _)| show_all || !target_name.starts_with('_'))
+        .collect();
+    targets.sort_by_key(|(target_name, _)| target_name.as_str());
+
+    let name_width = targets
+        .iter()
+        .map(|(target_name, _)| target_name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let default_targets = config.get_default_targets();
+
+    for (target_name, target) in targets {
+        let mut line = format!("{:width$}", target_name, width = name_width);
+        if let Some(description) = target.get_description() {
+            line.push_str(&format!("  {}", description));
+        }
+        if default_targets.contains(target_name) {
+            line.push_str("  (default)");
+        }
+        println!("{}", line.trim_end());
+
+        let dependencies = target.get_dependencies();
+        if !dependencies.is_empty() {
+            println!(
+                "{:width$}  depends on: {}",
+                "",
+                dependencies.join(", "),
+                width = name_width
+            );
+        }
+    }
+}
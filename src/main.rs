@@ -2,13 +2,25 @@ mod clean;
 mod cli;
 mod config;
 mod domain;
+mod dry_run;
 mod engine;
+mod list;
+mod paths;
 
 use anyhow::{Context, Result};
 use clean::clean_target_outputs;
 use config::Config;
-use engine::incremental::IncrementalRunner;
+use dry_run::print_execution_plan;
+use engine::command::OutputOptions;
+use engine::events::EventEmitter;
+use engine::incremental::{
+    ArtifactCache, IncrementalRunner, LocalCache, RemoteCache, RemoteCacheMode,
+    DEFAULT_MAX_ARTIFACT_SIZE,
+};
 use engine::Engine;
+use list::print_target_list;
+use std::env;
+use std::fs::File;
 use std::path::Path;
 
 fn main() -> Result<()> {
@@ -24,38 +36,163 @@ fn main() -> Result<()> {
     let config = Config::load(project_dir)?;
     let all_target_names = config.get_target_names();
 
+    let has_default_targets = !config.get_default_targets().is_empty();
+
     let arg_matches = cli::get_app()
         .mut_arg(cli::arg::TARGETS, |arg| {
-            arg.possible_values(&all_target_names)
-                .required_unless(cli::arg::CLEAN)
+            let arg = arg.possible_values(&all_target_names);
+            if has_default_targets {
+                arg
+            } else {
+                arg.required_unless_one(&[cli::arg::CLEAN, cli::arg::LIST])
+            }
         })
+        // Mutating an argument moves it to the end: the forwarded arguments must remain the last positional.
+        .mut_arg(cli::arg::ARGS, |arg| arg)
         .get_matches();
 
-    let requested_targets = arg_matches.values_of_lossy(cli::arg::TARGETS);
-    let targets = config.into_targets(project_dir, &requested_targets)?;
+    if arg_matches.is_present(cli::arg::LIST) {
+        print_target_list(&config, arg_matches.is_present(cli::arg::ALL));
+        return Ok(());
+    }
+
+    let jobs = match arg_matches.value_of(cli::arg::JOBS) {
+        Some(jobs) => jobs.parse()?,
+        None => config.get_jobs().unwrap_or_else(num_cpus::get),
+    };
 
     let checksum_dir = project_dir.join(".zinoma");
-    let incremental_runner = IncrementalRunner::new(&checksum_dir);
+    let local_cache = if config.get_cache().local {
+        Some(LocalCache::new(&checksum_dir))
+    } else {
+        None
+    };
+    let remote_cache = get_remote_cache(&config)?;
+
+    let requested_targets = arg_matches.values_of_lossy(cli::arg::TARGETS).or_else(|| {
+        if has_default_targets && !arg_matches.is_present(cli::arg::CLEAN) {
+            Some(config.get_default_targets().to_vec())
+        } else {
+            None
+        }
+    });
+    let args = arg_matches
+        .values_of_lossy(cli::arg::ARGS)
+        .unwrap_or_default();
+    let targets = config.into_targets(project_dir, &requested_targets, &args)?;
+
+    let incremental_runner = IncrementalRunner::new(
+        &checksum_dir,
+        arg_matches.is_present(cli::arg::EXPLAIN),
+        ArtifactCache::new(local_cache, remote_cache),
+    );
 
     if arg_matches.is_present(cli::arg::CLEAN) {
         incremental_runner.clean_checksums(&targets)?;
         clean_target_outputs(&targets)?;
     }
 
+    if arg_matches.is_present(cli::arg::DRY_RUN) {
+        return print_execution_plan(&targets, &incremental_runner);
+    }
+
     if requested_targets.is_some() {
-        let engine = Engine::new(targets, incremental_runner);
+        let message_format = arg_matches.value_of(cli::arg::MESSAGE_FORMAT);
+        let message_file = arg_matches.value_of(cli::arg::MESSAGE_FILE);
+        // The message file implies the JSON format, as it is the only format written to a file.
+        if message_file.is_some()
+            && arg_matches.occurrences_of(cli::arg::MESSAGE_FORMAT) > 0
+            && message_format != Some("json")
+        {
+            return Err(anyhow::anyhow!(
+                "--message-file requires --message-format json"
+            ));
+        }
+        let json_messages = message_format == Some("json") || message_file.is_some();
+        let output_options = OutputOptions {
+            prefix: !arg_matches.is_present(cli::arg::NO_OUTPUT_PREFIX),
+            stdout_to_stderr: json_messages && message_file.is_none(),
+        };
+        let event_emitter = match (json_messages, message_file) {
+            (false, _) => EventEmitter::disabled(),
+            (true, None) => EventEmitter::new(Box::new(std::io::stdout())),
+            (true, Some(message_file)) => {
+                EventEmitter::new(Box::new(File::create(message_file).with_context(|| {
+                    format!("Failed to create message file {}", message_file)
+                })?))
+            }
+        };
+
+        let engine = Engine::new(
+            targets,
+            incremental_runner,
+            jobs,
+            output_options,
+            event_emitter,
+        );
 
-        crossbeam::scope(|scope| {
+        let build_summary = crossbeam::scope(|scope| {
             if arg_matches.is_present(cli::arg::WATCH) {
-                engine.watch(scope).with_context(|| "Watch error")
+                engine
+                    .watch(
+                        scope,
+                        arg_matches.is_present(cli::arg::RESTART_BUILDS),
+                        &Config::get_file_path(project_dir),
+                        || {
+                            Config::load(project_dir)?.into_targets(
+                                project_dir,
+                                &requested_targets,
+                                &args,
+                            )
+                        },
+                    )
+                    .with_context(|| "Watch error")
+                    .map(|_| None)
             } else {
-                engine.build(scope).with_context(|| "Build error")
+                engine
+                    .build(scope, arg_matches.is_present(cli::arg::KEEP_GOING))
+                    .with_context(|| "Build error")
+                    .map(Some)
             }
         })
         .map_err(|_| {
             anyhow::anyhow!("Unknown crossbeam parallelism failure (thread panicked)")
         })??;
+
+        if let Some(build_summary) = build_summary {
+            eprint!("{}", build_summary);
+            build_summary.into_result().with_context(|| "Build error")?;
+        }
     }
 
     Ok(())
 }
+
+/// The remote cache can be configured in zinoma.yml,
+/// and overridden by the `ZINOMA_REMOTE_CACHE_URL` and `ZINOMA_REMOTE_CACHE_MODE` environment variables.
+fn get_remote_cache(config: &Config) -> Result<Option<RemoteCache>> {
+    let remote_config = config.get_cache().remote.as_ref();
+
+    let url = match env::var("ZINOMA_REMOTE_CACHE_URL") {
+        Ok(url) => url,
+        Err(_) => match remote_config {
+            Some(remote_config) => remote_config.url.clone(),
+            None => return Ok(None),
+        },
+    };
+
+    let mode = match env::var("ZINOMA_REMOTE_CACHE_MODE") {
+        Ok(mode) => Some(mode),
+        Err(_) => remote_config.and_then(|remote_config| remote_config.mode.clone()),
+    };
+    let mode = match mode {
+        Some(mode) => RemoteCacheMode::parse(&mode).with_context(|| "Invalid remote cache")?,
+        None => RemoteCacheMode::Read,
+    };
+
+    let max_artifact_size = remote_config
+        .and_then(|remote_config| remote_config.max_artifact_size)
+        .unwrap_or(DEFAULT_MAX_ARTIFACT_SIZE);
+
+    Ok(Some(RemoteCache::new(&url, mode, max_artifact_size)))
+}
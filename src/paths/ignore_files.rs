@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Names of the ignore files read in every directory of the project, by increasing precedence.
+const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Name of the ignore file read at the root of the project. It takes precedence over the other ignore files.
+const PROJECT_IGNORE_FILE_NAME: &str = ".zinomaignore";
+
+/// Ignore rules of a project, read from its `.gitignore`, `.ignore` and `.zinomaignore` files.
+///
+/// Ignore files are read lazily, and kept until they are modified (see [`IgnoreFiles::reload`]).
+#[derive(Debug)]
+pub struct IgnoreFiles {
+    project_dir: PathBuf,
+    gitignores: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl IgnoreFiles {
+    pub fn new(project_dir: &Path) -> Result<Self> {
+        let current_dir = env::current_dir().with_context(|| "Failed to get current directory")?;
+        Ok(Self {
+            project_dir: super::to_absolute_path(project_dir, &current_dir),
+            gitignores: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Checks whether the file or directory is ignored by the ignore files of the project.
+    ///
+    /// Only the path itself is considered, not its parent directories.
+    pub fn is_ignored(&self, absolute_path: &Path, is_dir: bool) -> bool {
+        if absolute_path.file_name() == Some(".git".as_ref()) {
+            return true;
+        }
+
+        if !absolute_path.starts_with(&self.project_dir) {
+            return false;
+        }
+
+        let mut dirs: Vec<_> = absolute_path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.project_dir))
+            .collect();
+        dirs.reverse();
+
+        let mut is_ignored = false;
+        for dir in dirs {
+            if let Some(gitignore) = self.get_gitignore(dir) {
+                let matched = gitignore.matched(absolute_path, is_dir);
+                if matched.is_ignore() {
+                    is_ignored = true;
+                } else if matched.is_whitelist() {
+                    is_ignored = false;
+                }
+            }
+        }
+        is_ignored
+    }
+
+    /// Forgets the ignore files read so far, if the path is one of them.
+    pub fn reload(&self, absolute_path: &Path) {
+        let is_ignore_file = match absolute_path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => {
+                IGNORE_FILE_NAMES.contains(&file_name) || file_name == PROJECT_IGNORE_FILE_NAME
+            }
+            None => false,
+        };
+        if is_ignore_file {
+            self.gitignores.lock().unwrap().clear();
+        }
+    }
+
+    fn get_gitignore(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut gitignores = self.gitignores.lock().unwrap();
+        if let Some(gitignore) = gitignores.get(dir) {
+            return gitignore.clone();
+        }
+
+        let mut ignore_files: Vec<_> = IGNORE_FILE_NAMES
+            .iter()
+            .map(|file_name| dir.join(file_name))
+            .collect();
+        if dir == self.project_dir {
+            ignore_files.push(dir.join(PROJECT_IGNORE_FILE_NAME));
+        }
+        let gitignore = build_gitignore(dir, &ignore_files).map(Arc::new);
+        gitignores.insert(dir.to_path_buf(), gitignore.clone());
+        gitignore
+    }
+}
+
+fn build_gitignore(dir: &Path, ignore_files: &[PathBuf]) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut has_ignore_file = false;
+
+    for ignore_file in ignore_files.iter().filter(|file| file.is_file()) {
+        has_ignore_file = true;
+        if let Some(e) = builder.add(ignore_file) {
+            log::warn!("Failed to read {}: {}", ignore_file.display(), e);
+        }
+    }
+
+    if !has_ignore_file {
+        return None;
+    }
+
+    match builder.build() {
+        Ok(gitignore) => Some(gitignore),
+        Err(e) => {
+            log::warn!("Failed to parse ignore files in {}: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreFiles;
+    use std::fs;
+
+    #[test]
+    fn test_ignore_files_should_apply_by_increasing_precedence() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let project_dir = project_dir.path();
+        fs::create_dir_all(project_dir.join("src/sub")).unwrap();
+        fs::write(project_dir.join(".gitignore"), "*.bak\n*.log\n").unwrap();
+        fs::write(project_dir.join("src/sub/.ignore"), "!keep.bak\n").unwrap();
+        fs::write(project_dir.join(".zinomaignore"), "!debug.log\n").unwrap();
+
+        let ignore_files = IgnoreFiles::new(project_dir).unwrap();
+
+        assert!(!ignore_files.is_ignored(&project_dir.join("src/main.rs"), false));
+        assert!(ignore_files.is_ignored(&project_dir.join("src/main.rs.bak"), false));
+        assert!(!ignore_files.is_ignored(&project_dir.join("src/sub/keep.bak"), false));
+        assert!(ignore_files.is_ignored(&project_dir.join("src/sub/other.bak"), false));
+        assert!(ignore_files.is_ignored(&project_dir.join("src/app.log"), false));
+        assert!(!ignore_files.is_ignored(&project_dir.join("debug.log"), false));
+        assert!(ignore_files.is_ignored(&project_dir.join(".git"), true));
+
+        fs::write(project_dir.join(".gitignore"), "*.log\n").unwrap();
+        assert!(ignore_files.is_ignored(&project_dir.join("src/main.rs.bak"), false));
+        ignore_files.reload(&project_dir.join(".gitignore"));
+        assert!(!ignore_files.is_ignored(&project_dir.join("src/main.rs.bak"), false));
+    }
+}
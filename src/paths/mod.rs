@@ -0,0 +1,468 @@
+mod ignore_files;
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+pub use ignore_files::IgnoreFiles;
+use std::collections::HashSet;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+/// Set of files and directories, described by paths and glob patterns.
+///
+/// A directory matched by a path or a pattern includes all of its content.
+/// Patterns starting with `!` exclude the matching files and directories.
+#[derive(Clone, Debug, Default)]
+pub struct Paths {
+    groups: Vec<PathGroup>,
+}
+
+/// Paths declared together, in the same list. Exclusions only apply within their group.
+#[derive(Clone, Debug)]
+struct PathGroup {
+    base_dir: PathBuf,
+    patterns: Vec<String>,
+    includes: Vec<PathPattern>,
+    excludes: Vec<PathPattern>,
+    ignore_files: Option<Arc<IgnoreFiles>>,
+}
+
+#[derive(Clone, Debug)]
+enum PathPattern {
+    Path {
+        path: PathBuf,
+        absolute_path: PathBuf,
+    },
+    Glob {
+        root: PathBuf,
+        absolute_root: PathBuf,
+        matcher: GlobMatcher,
+    },
+}
+
+impl Paths {
+    /// Parses the paths and glob patterns, relative to the base directory.
+    pub fn new(base_dir: &Path, patterns: &[String]) -> Result<Self> {
+        if patterns.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let current_dir = env::current_dir().with_context(|| "Failed to get current directory")?;
+        let mut includes = Vec::new();
+        let mut excludes = Vec::new();
+
+        for pattern in patterns {
+            let (is_exclusion, path_pattern) = match pattern.strip_prefix('!') {
+                Some(excluded_pattern) => (true, excluded_pattern),
+                None => (false, pattern.as_str()),
+            };
+            let path_pattern = PathPattern::new(base_dir, path_pattern, &current_dir)
+                .with_context(|| format!("Invalid path pattern {}", pattern))?;
+
+            if is_exclusion {
+                excludes.push(path_pattern);
+            } else {
+                includes.push(path_pattern);
+            }
+        }
+
+        Ok(Self {
+            groups: vec![PathGroup {
+                base_dir: base_dir.to_path_buf(),
+                patterns: patterns.to_vec(),
+                includes,
+                excludes,
+                ignore_files: None,
+            }],
+        })
+    }
+
+    /// Skips the files ignored by the ignore files, when found while traversing directories.
+    ///
+    /// The paths explicitly listed are never ignored.
+    pub fn with_ignore_files(mut self, ignore_files: Arc<IgnoreFiles>) -> Self {
+        for group in self.groups.iter_mut() {
+            group.ignore_files = Some(ignore_files.clone());
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.iter().all(|group| group.includes.is_empty())
+    }
+
+    /// Adds the files of `other` to this set. Exclusions of `other` do not apply to the files of this set.
+    pub fn extend(&mut self, other: &Paths) {
+        for group in &other.groups {
+            if !self.groups.contains(group) {
+                self.groups.push(group.clone());
+            }
+        }
+    }
+
+    /// Lists the absolute paths under which the matching files can be found (e.g. to watch them).
+    pub fn get_roots(&self) -> Vec<&Path> {
+        self.groups
+            .iter()
+            .flat_map(|group| group.includes.iter().map(PathPattern::get_absolute_root))
+            .collect()
+    }
+
+    /// Checks whether the file or directory is part of this set.
+    pub fn matches(&self, path: &Path) -> bool {
+        match env::current_dir() {
+            Ok(current_dir) => {
+                let absolute_path = to_absolute_path(path, &current_dir);
+                self.groups
+                    .iter()
+                    .any(|group| group.matches(&absolute_path))
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Makes the ignore files be read again, if the modified path is one of them.
+    pub fn reload_ignore_files(&self, modified_path: &Path) {
+        if let Ok(current_dir) = env::current_dir() {
+            let absolute_path = to_absolute_path(modified_path, &current_dir);
+            for ignore_files in self
+                .groups
+                .iter()
+                .filter_map(|group| group.ignore_files.as_ref())
+            {
+                ignore_files.reload(&absolute_path);
+            }
+        }
+    }
+
+    /// Lists all the files of this set.
+    pub fn list_files(&self) -> Result<HashSet<PathBuf>> {
+        let current_dir = env::current_dir().with_context(|| "Failed to get current directory")?;
+        let mut files = HashSet::new();
+
+        for group in &self.groups {
+            for include in &group.includes {
+                let root = include.get_root();
+                if let PathPattern::Glob { .. } = include {
+                    if !root.exists() {
+                        continue;
+                    }
+                }
+
+                let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+                    let absolute_path = to_absolute_path(entry.path(), &current_dir);
+                    if group.is_excluded(&absolute_path) {
+                        return false;
+                    }
+                    match &group.ignore_files {
+                        Some(ignore_files) if entry.depth() > 0 => {
+                            !ignore_files.is_ignored(&absolute_path, entry.file_type().is_dir())
+                        }
+                        _ => true,
+                    }
+                });
+                for entry in walker {
+                    let path = entry
+                        .with_context(|| {
+                            format!("Failed to traverse directory {}", root.display())
+                        })?
+                        .path()
+                        .to_path_buf();
+                    // Excluded and ignored entries were already filtered out while walking.
+                    if path.is_file() && group.is_included(&to_absolute_path(&path, &current_dir)) {
+                        files.insert(path);
+                    }
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Lists the files and directories of this set, without listing the content of the matching directories.
+    ///
+    /// When some files are excluded from a directory, its other files are listed individually instead.
+    pub fn list_top_level_entries(&self) -> Result<Vec<PathBuf>> {
+        let current_dir = env::current_dir().with_context(|| "Failed to get current directory")?;
+        let mut entries = Vec::new();
+
+        for group in &self.groups {
+            for include in &group.includes {
+                let root = include.get_root();
+                if !root.exists() {
+                    continue;
+                }
+
+                let mut walker = WalkDir::new(root).into_iter();
+                while let Some(entry) = walker.next() {
+                    let entry = entry.with_context(|| {
+                        format!("Failed to traverse directory {}", root.display())
+                    })?;
+                    let absolute_path = to_absolute_path(entry.path(), &current_dir);
+                    let is_dir = entry.file_type().is_dir();
+
+                    if group.is_excluded(&absolute_path) {
+                        if is_dir {
+                            walker.skip_current_dir();
+                        }
+                    } else if group.is_included(&absolute_path)
+                        && (group.excludes.is_empty() || !is_dir)
+                    {
+                        entries.push(entry.path().to_path_buf());
+                        if is_dir {
+                            walker.skip_current_dir();
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+impl Paths {
+    /// Hashes the definition of the paths, with their base directories relative to `dir` when located in it.
+    pub fn hash_relative_to<H: Hasher>(&self, dir: &Path, state: &mut H) {
+        for group in &self.groups {
+            group
+                .base_dir
+                .strip_prefix(dir)
+                .unwrap_or(&group.base_dir)
+                .hash(state);
+            group.patterns.hash(state);
+            group.ignore_files.is_some().hash(state);
+        }
+    }
+}
+
+impl PartialEq for PathGroup {
+    fn eq(&self, other: &Self) -> bool {
+        self.base_dir == other.base_dir
+            && self.patterns == other.patterns
+            && self.ignore_files.is_some() == other.ignore_files.is_some()
+    }
+}
+
+impl PathGroup {
+    fn matches(&self, absolute_path: &Path) -> bool {
+        let is_included = match &self.ignore_files {
+            Some(ignore_files) => self.includes.iter().any(|include| {
+                include.matches(absolute_path)
+                    && !is_ignored_below(ignore_files, include.get_absolute_root(), absolute_path)
+            }),
+            None => self.is_included(absolute_path),
+        };
+
+        is_included && !self.is_excluded(absolute_path)
+    }
+
+    fn is_included(&self, absolute_path: &Path) -> bool {
+        self.includes
+            .iter()
+            .any(|include| include.matches(absolute_path))
+    }
+
+    fn is_excluded(&self, absolute_path: &Path) -> bool {
+        self.excludes
+            .iter()
+            .any(|exclude| exclude.matches(absolute_path))
+    }
+}
+
+impl PathPattern {
+    fn new(base_dir: &Path, pattern: &str, current_dir: &Path) -> Result<Self> {
+        let pattern = pattern.trim_end_matches('/');
+        let path = base_dir.join(pattern);
+
+        if !is_glob(pattern) {
+            let absolute_path = to_absolute_path(&path, current_dir);
+            return Ok(Self::Path {
+                path,
+                absolute_path,
+            });
+        }
+
+        let mut root = base_dir.to_path_buf();
+        let mut remaining_pattern = Vec::new();
+        for component in pattern.split('/') {
+            if remaining_pattern.is_empty() && !is_glob(component) {
+                root.push(component);
+            } else {
+                remaining_pattern.push(component);
+            }
+        }
+
+        let absolute_root = to_absolute_path(&root, current_dir);
+        let mut glob_root = absolute_root.to_string_lossy().to_string();
+        if MAIN_SEPARATOR != '/' {
+            glob_root = glob_root.replace(MAIN_SEPARATOR, "/");
+        }
+        let glob = format!(
+            "{}/{}",
+            globset::escape(glob_root.trim_end_matches('/')),
+            remaining_pattern.join("/")
+        );
+        let matcher = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .build()?
+            .compile_matcher();
+
+        Ok(Self::Glob {
+            root,
+            absolute_root,
+            matcher,
+        })
+    }
+
+    fn get_root(&self) -> &Path {
+        match self {
+            Self::Path { path, .. } => path,
+            Self::Glob { root, .. } => root,
+        }
+    }
+
+    fn get_absolute_root(&self) -> &Path {
+        match self {
+            Self::Path { absolute_path, .. } => absolute_path,
+            Self::Glob { absolute_root, .. } => absolute_root,
+        }
+    }
+
+    /// Checks whether the path, or one of its parent directories, matches this pattern.
+    fn matches(&self, absolute_path: &Path) -> bool {
+        match self {
+            Self::Path {
+                absolute_path: pattern_path,
+                ..
+            } => absolute_path.starts_with(pattern_path),
+            Self::Glob { matcher, .. } => absolute_path
+                .ancestors()
+                .any(|ancestor| matcher.is_match(ancestor)),
+        }
+    }
+}
+
+/// Checks whether the path, or one of its parent directories below `root`, is ignored.
+fn is_ignored_below(ignore_files: &IgnoreFiles, root: &Path, absolute_path: &Path) -> bool {
+    absolute_path
+        .ancestors()
+        .take_while(|path| *path != root && path.starts_with(root))
+        .any(|path| ignore_files.is_ignored(path, path != absolute_path || path.is_dir()))
+}
+
+/// Only `*` and `?` make a pattern a glob, so that paths like `pages/[id].tsx` keep being matched literally.
+///
+/// From the first glob component of a pattern, `[...]` and `{...}` keep their glob meaning, unless escaped with `\`.
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(&['*', '?'][..])
+}
+
+/// Turns the path into an absolute path, and removes its `.` and `..` components.
+pub fn to_absolute_path(path: &Path, current_dir: &Path) -> PathBuf {
+    let mut absolute_path = PathBuf::new();
+    for component in current_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute_path.pop();
+            }
+            component => absolute_path.push(component),
+        }
+    }
+    absolute_path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Paths;
+    use std::path::{Path, PathBuf};
+
+    /// The project directory is absolute on every platform, but does not need to exist.
+    fn project_dir() -> PathBuf {
+        std::env::temp_dir().join("project")
+    }
+
+    fn project_path(path: &str) -> PathBuf {
+        path.split('/')
+            .fold(project_dir(), |project_path, component| {
+                project_path.join(component)
+            })
+    }
+
+    fn build_paths(patterns: &[&str]) -> Paths {
+        let patterns: Vec<_> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        Paths::new(&project_dir(), &patterns).unwrap()
+    }
+
+    #[test]
+    fn plain_path_should_match_its_content() {
+        let paths = build_paths(&["src"]);
+
+        assert!(paths.matches(&project_path("src")));
+        assert!(paths.matches(&project_path("src/deep/main.rs")));
+        assert!(!paths.matches(&project_path("srcs/main.rs")));
+        assert!(!paths.matches(&project_path("README.md")));
+    }
+
+    #[test]
+    fn glob_pattern_should_match_files() {
+        let paths = build_paths(&["src/**/*.ts"]);
+
+        assert!(paths.matches(&project_path("src/index.ts")));
+        assert!(paths.matches(&project_path("src/deep/dir/index.ts")));
+        assert!(!paths.matches(&project_path("src/index.js")));
+        assert!(!paths.matches(&project_path("lib/index.ts")));
+        assert_eq!(paths.get_roots(), vec![project_path("src")]);
+    }
+
+    #[test]
+    fn brackets_should_not_make_a_glob_pattern() {
+        let paths = build_paths(&["pages/[id].tsx", "{{templates}}/*.html"]);
+
+        assert!(paths.matches(&project_path("pages/[id].tsx")));
+        assert!(!paths.matches(&project_path("pages/i.tsx")));
+        assert!(paths.matches(&project_path("{{templates}}/index.html")));
+        assert_eq!(
+            paths.get_roots(),
+            vec![
+                project_path("pages/[id].tsx"),
+                project_path("{{templates}}")
+            ]
+        );
+
+        let paths = build_paths(&["app/[id]/*.tsx", "lib/**/\\[id\\].ts"]);
+        assert!(paths.matches(&project_path("app/[id]/page.tsx")));
+        assert!(paths.matches(&project_path("lib/deep/[id].ts")));
+        assert!(!paths.matches(&project_path("lib/deep/i.ts")));
+    }
+
+    #[test]
+    fn exclusions_should_apply_to_files_and_directories() {
+        let paths = build_paths(&[".", "!node_modules", "!**/*.snap", "!target/"]);
+
+        assert!(paths.matches(&project_path("src/index.ts")));
+        assert!(!paths.matches(&project_path("node_modules/lib/index.js")));
+        assert!(!paths.matches(&project_path("src/__snapshots__/index.snap")));
+        assert!(!paths.matches(&project_path("target/debug/main")));
+    }
+
+    #[test]
+    fn exclusions_should_not_apply_to_extended_paths() {
+        let mut paths = build_paths(&["src", "!src/generated"]);
+        paths.extend(&build_paths(&["src/generated"]));
+
+        assert!(paths.matches(&project_path("src/generated/schema.ts")));
+    }
+
+    #[test]
+    fn relative_paths_should_be_resolved_from_current_dir() {
+        let paths = Paths::new(Path::new("."), &["src/*.rs".to_string()]).unwrap();
+        let current_dir = std::env::current_dir().unwrap();
+
+        assert!(paths.matches(Path::new("./src/main.rs")));
+        assert!(paths.matches(&current_dir.join("src/main.rs")));
+    }
+}